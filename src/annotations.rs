@@ -0,0 +1,167 @@
+use std::{fs, io, path::Path};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::{
+    tests::{TestCheck, TestData, Tests},
+    validate,
+};
+
+/// Adds the tests annotated in a program to the tests of the test file. A
+/// program that doesn't exist has none.
+pub fn add(tests: &mut Tests, ens_file: &Path) -> Result<(), String> {
+    let ens = match fs::read(ens_file) {
+        Ok(ens) => ens,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+    for (group, new) in parse(&String::from_utf8_lossy(&ens))? {
+        tests.add(&group, new)?;
+    }
+    Ok(())
+}
+
+/// Tests written in the program itself, as comments above the label they run:
+///
+/// ```text
+/// ; @test r29=12 setup r2=3 r3=9
+/// ; @test r29=0 setup r2=0 m(0x1000)="Hola" name=empty
+/// sum:
+/// ```
+///
+/// Checks come first, and the values after `setup` are set before running.
+/// Memory takes text between quotes, a list of bytes in brackets or a word.
+/// Any other `key=value` is a setting of the test, such as `name`, `tags=[a]`
+/// or `xfail="reason"`. Each label gets a group, with the tests named
+/// `{label}_{index}` unless they are given a name.
+pub fn parse(ens: &str) -> Result<Vec<(String, Vec<TestData>)>, String> {
+    let mut groups: Vec<(String, Vec<TestData>)> = vec![];
+    let mut pending: Vec<(usize, &str)> = vec![];
+    for (index, line) in ens.lines().enumerate() {
+        let line_number = index + 1;
+        let trimmed = line.trim();
+        if let Some(comment) = trimmed.strip_prefix(';') {
+            match comment.trim_start().strip_prefix("@test") {
+                Some(annotation)
+                    if annotation.is_empty() || annotation.starts_with(char::is_whitespace) =>
+                {
+                    pending.push((line_number, annotation))
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if trimmed.is_empty() || pending.is_empty() {
+            continue;
+        }
+        let Some(label) = label(trimmed) else {
+            return Err(format!(
+                "line {}: @test has to be above a label",
+                pending[0].0
+            ));
+        };
+        let start = groups
+            .iter()
+            .find(|(group, _)| group == label)
+            .map_or(0, |(_, tests)| tests.len());
+        let tests = pending
+            .drain(..)
+            .enumerate()
+            .map(|(i, (line_number, annotation))| {
+                test(label, start + i, annotation).map_err(|e| format!("line {line_number}: {e}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match groups.iter_mut().find(|(group, _)| group == label) {
+            Some((_, group)) => group.extend(tests),
+            None => groups.push((label.to_string(), tests)),
+        }
+    }
+    match pending.first() {
+        Some((line_number, _)) => Err(format!("line {line_number}: @test has to be above a label")),
+        None => Ok(groups),
+    }
+}
+
+/// The label a line starts with, if it does
+fn label(line: &str) -> Option<&str> {
+    let (label, _) = line.split_once(':')?;
+    (!label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+    .then_some(label)
+}
+
+fn test(label: &str, index: usize, annotation: &str) -> Result<TestData, String> {
+    let mut test = Mapping::new();
+    test.insert("entrypoint".into(), label.into());
+    test.insert("name".into(), format!("{label}_{index}").into());
+    let (mut checks, mut setup) = (Mapping::new(), Mapping::new());
+    let mut in_setup = false;
+    for token in tokens(annotation)? {
+        if token == "setup" {
+            in_setup = true;
+            continue;
+        }
+        let Some((key, value)) = token.split_once('=') else {
+            return Err(format!("expected key=value, found {token}"));
+        };
+        let values = if in_setup { &mut setup } else { &mut checks };
+        if key.starts_with(['r', 'R']) && key[1..].parse::<u8>().is_ok() {
+            values.insert(key.into(), yaml(value)?);
+        } else if key.starts_with(['m', 'M']) && key[1..].starts_with('(') {
+            let kind = match value.chars().next() {
+                Some('"' | '\'') => "text",
+                Some('[') => "bytes",
+                _ => "word",
+            };
+            values.insert(
+                key.into(),
+                Mapping::from_iter([(kind.into(), yaml(value)?)]).into(),
+            );
+        } else {
+            test.insert(key.into(), yaml(value)?);
+        }
+    }
+    test.insert("setup".into(), setup.into());
+    test.insert("checks".into(), checks.into());
+    let test: TestData = serde_yaml::from_value(test.into()).map_err(|e| e.to_string())?;
+    for check in test.get_setup() {
+        if let TestCheck::Memory(address, data) = check {
+            validate::setup_memory(*address, data)?;
+        }
+    }
+    Ok(test)
+}
+
+fn yaml(value: &str) -> Result<Value, String> {
+    serde_yaml::from_str(value).map_err(|e| format!("{value}: {e}"))
+}
+
+/// The words of an annotation, keeping together what is between quotes or brackets
+fn tokens(annotation: &str) -> Result<Vec<&str>, String> {
+    let mut res = vec![];
+    let mut start = None;
+    let mut closing = None;
+    for (i, c) in annotation.char_indices() {
+        match (closing, c) {
+            (Some(close), c) if c == close => closing = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => closing = Some(c),
+            (None, '[') => closing = Some(']'),
+            (None, c) if c.is_whitespace() => {
+                if let Some(s) = start.take() {
+                    res.push(&annotation[s..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(close) = closing {
+        return Err(format!("missing {close}"));
+    }
+    res.extend(start.map(|s| &annotation[s..]));
+    Ok(res)
+}
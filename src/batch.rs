@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    grading::{csv_field, Scheme, Score},
+    job::TestEnv,
+    runner::{GroupResult, RunError},
+    tests::TestData,
+    threadpool::{Event, FinishStatus, ThreadPool},
+};
+
+/// A student's program, found in the submissions directory
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub name: String,
+    /// `None` if a folder had no `.ens` file in it
+    pub ens_file: Option<PathBuf>,
+}
+
+/// Results of the tests run against a submission
+#[derive(Debug)]
+pub struct SubmissionResult {
+    pub name: String,
+    pub groups: Vec<GroupResult>,
+    /// Why the tests couldn't be run at all
+    pub error: Option<String>,
+}
+
+/// Finds the submissions in `dir`: every `.ens` file, named after the file, and
+/// every folder, named after the folder, with the `.ens` file in it.
+///
+/// If a folder has several `.ens` files the one named `preferred` is used, or
+/// else the first one by name. Two submissions can't have the same name, such
+/// as `alice.ens` and `alice/`.
+pub fn find_submissions(dir: &Path, preferred: Option<&OsStr>) -> io::Result<Vec<Submission>> {
+    let is_ens = |path: &Path| {
+        path.extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ens"))
+    };
+    let mut submissions = vec![];
+    let mut seen = HashMap::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let submission = if path.is_dir() {
+            let mut files = fs::read_dir(&path)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()?;
+            files.retain(|f| f.is_file() && is_ens(f));
+            files.sort();
+            let ens_file = files
+                .iter()
+                .find(|f| preferred.is_some() && f.file_name() == preferred)
+                .or_else(|| files.first())
+                .cloned();
+            Submission {
+                name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                ens_file,
+            }
+        } else if is_ens(&path) {
+            Submission {
+                name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+                ens_file: Some(path.clone()),
+            }
+        } else {
+            continue;
+        };
+        if let Some(first) = seen.insert(submission.name.clone(), path.clone()) {
+            return Err(io::Error::other(format!(
+                "{} and {} are both the submission {}",
+                first.display(),
+                path.display(),
+                submission.name
+            )));
+        }
+        submissions.push(submission);
+    }
+    submissions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(submissions)
+}
+
+/// Runs the selected tests against every submission, all of them sharing the
+/// same thread pool, while printing the progress on stderr
+pub fn run(
+    submissions: &[Submission],
+    groups: &[(String, Vec<(String, &TestData)>)],
+    env: &TestEnv,
+    threads: usize,
+) -> Vec<SubmissionResult> {
+    let mut errors = HashMap::new();
+    let mut sources = HashMap::new();
+    for submission in submissions {
+        match &submission.ens_file {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(contents) => {
+                    sources.insert(
+                        submission.name.clone(),
+                        (env.for_submission(&submission.name), contents),
+                    );
+                }
+                Err(e) => {
+                    errors.insert(submission.name.clone(), format!("{}: {e}", path.display()));
+                }
+            },
+            None => {
+                errors.insert(submission.name.clone(), "no .ens file found".to_string());
+            }
+        }
+    }
+    let sources = Arc::new(sources);
+
+    let mut threadpool = ThreadPool::<(String, usize, String, String, _), _>::new(
+        move |(submission, group_id, group, name, test): (
+            String,
+            usize,
+            String,
+            String,
+            TestData,
+        ),
+              _id| {
+            let (env, contents) = &sources[&submission];
+            let result = env.run(contents, &group, &name, &test);
+            (submission, group_id, group, name, result)
+        },
+        threads,
+    );
+
+    let mut total = 0;
+    for submission in submissions.iter().filter(|s| !errors.contains_key(&s.name)) {
+        for (i, (group, tests)) in groups.iter().enumerate() {
+            for (test_name, test) in tests {
+                threadpool.send_data((
+                    submission.name.clone(),
+                    i,
+                    group.clone(),
+                    test_name.clone(),
+                    (*test).clone(),
+                ));
+                total += 1;
+            }
+        }
+    }
+    threadpool.finish();
+
+    let mut finished = 0;
+    while total != 0 && threadpool.is_finished() != FinishStatus::Finished {
+        threadpool.update_status();
+        let events = threadpool.take_events();
+        if !events.is_empty() {
+            finished += events
+                .iter()
+                .filter(|e| matches!(e, Event::Finished { .. }))
+                .count();
+            eprint!("\r  {finished}/{total} tests run");
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    if total != 0 {
+        eprintln!();
+    }
+
+    let mut results: Vec<SubmissionResult> = submissions
+        .iter()
+        .map(|s| SubmissionResult {
+            name: s.name.clone(),
+            groups: vec![],
+            error: errors.remove(&s.name),
+        })
+        .collect();
+    let mut index = 0;
+    for (submission, group_id, group, name, result) in threadpool.results() {
+        while results[index].name != submission {
+            index += 1;
+        }
+        let groups = &mut results[index].groups;
+        match groups.last_mut() {
+            Some(last) if last.id == group_id => last.tests.push((name, result)),
+            _ => groups.push(GroupResult {
+                id: group_id,
+                name: group,
+                tests: vec![(name, result)],
+            }),
+        }
+    }
+    results
+}
+
+/// Writes one row per submission with its score, why it didn't assemble if it
+/// didn't, the score of each group and the result of each of the `tests` run
+pub fn write_gradebook(
+    path: &Path,
+    results: &[SubmissionResult],
+    tests: &[(String, Vec<(String, &TestData)>)],
+    scheme: &Scheme,
+) -> io::Result<()> {
+    let columns: Vec<(String, Vec<String>)> = tests
+        .iter()
+        .map(|(group, tests)| {
+            (
+                group.clone(),
+                tests.iter().map(|(name, _)| name.clone()).collect(),
+            )
+        })
+        .collect();
+
+    let mut out = io::BufWriter::new(fs::File::create(path)?);
+    write!(out, "submission,score,max,assembler_error")?;
+    for (group, _) in &columns {
+        write!(out, ",{}", csv_field(group))?;
+    }
+    for (group, tests) in &columns {
+        for test in tests {
+            write!(out, ",{}", csv_field(&format!("{group}/{test}")))?;
+        }
+    }
+    writeln!(out)?;
+
+    for result in results {
+        // Submissions that couldn't be run still miss all the points
+        let score = match result.error {
+            Some(_) => Score::failed(scheme, tests),
+            None => Score::new(scheme, &result.groups),
+        };
+        let error = result
+            .error
+            .clone()
+            .or_else(|| assembler_error(&result.groups));
+        write!(
+            out,
+            "{},{},{},{}",
+            csv_field(&result.name),
+            score.score,
+            score.max,
+            csv_field(error.as_deref().unwrap_or(""))
+        )?;
+        for (group, _) in &columns {
+            match score.groups.iter().find(|g| &g.name == group) {
+                Some(g) => write!(out, ",{}", g.score)?,
+                None => write!(out, ",")?,
+            }
+        }
+        for (group, tests) in &columns {
+            let group = result.groups.iter().find(|g| &g.name == group);
+            for test in tests {
+                let status = group
+                    .and_then(|g| g.tests.iter().find(|(name, _)| name == test))
+                    .map_or("", |(_, r)| r.status());
+                write!(out, ",{status}")?;
+            }
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+/// The first line the assembler complained with, if it failed on any test
+fn assembler_error(groups: &[GroupResult]) -> Option<String> {
+    groups
+        .iter()
+        .flat_map(|g| &g.tests)
+        .find_map(|(_, result)| match &result.result {
+            Err(e @ RunError::CompileExec(_)) => Some(e.to_string()),
+            Err(e @ RunError::Compile(output)) => [&output.stderr, &output.stdout]
+                .into_iter()
+                .flat_map(|text| {
+                    String::from_utf8_lossy(text)
+                        .lines()
+                        .map(str::trim)
+                        .find(|line| !line.is_empty())
+                        .map(ToString::to_string)
+                })
+                .next()
+                .or_else(|| Some(e.to_string())),
+            _ => None,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter::Filter, runner::TestResult, tests::Tests};
+
+    fn result(passed: bool) -> TestResult {
+        let mut result = TestResult::skipped(String::new());
+        result.skipped = None;
+        if !passed {
+            result.result = Err(RunError::RegistersFailed(vec![], None));
+        }
+        result
+    }
+
+    #[test]
+    fn gradebook_gives_every_submission_the_same_max() {
+        let yaml = "\
+a:
+  - r2: 1
+  - r2: 2
+b:
+  - r2: 1
+  - skip: not written yet
+    checks:
+      r2: 1
+";
+        let tests = Tests::new(serde_yaml::from_str(yaml).unwrap(), &Default::default()).unwrap();
+        let scheme = Scheme::new(&tests);
+        let selected = Filter::default().select(&tests);
+        let results = [
+            SubmissionResult {
+                name: "alice".to_string(),
+                groups: vec![
+                    GroupResult {
+                        id: 0,
+                        name: "a".to_string(),
+                        tests: vec![
+                            ("test_a0".to_string(), result(true)),
+                            ("test_a1".to_string(), result(false)),
+                        ],
+                    },
+                    GroupResult {
+                        id: 1,
+                        name: "b".to_string(),
+                        tests: vec![
+                            ("test_b0".to_string(), result(true)),
+                            (
+                                "test_b1".to_string(),
+                                TestResult::skipped("not written yet".to_string()),
+                            ),
+                        ],
+                    },
+                ],
+                error: None,
+            },
+            SubmissionResult {
+                name: "bob".to_string(),
+                groups: vec![],
+                error: Some("no .ens file found".to_string()),
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("gradebook-{}.csv", std::process::id()));
+        write_gradebook(&path, &results, &selected, &scheme).unwrap();
+        let gradebook = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            gradebook,
+            "\
+submission,score,max,assembler_error,a,b,a/test_a0,a/test_a1,b/test_b0,b/test_b1
+alice,2,3,,1,1,passed,failed,passed,skipped
+bob,0,3,no .ens file found,0,0,,,,
+"
+        );
+    }
+}
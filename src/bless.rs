@@ -0,0 +1,387 @@
+use std::{fs, io, ops::Range, path::Path};
+
+use crate::{
+    emulator::MemoryData,
+    locate::{content, indent, strip_comment, test_spans},
+    runner::{DataFailure, GroupResult, RunError},
+};
+
+/// Names of the kinds of memory data, as written in the test file
+const MEMORY_KINDS: [&str; 6] = ["bytes", "byte", "half_word", "word", "double_word", "text"];
+
+/// Writes the values the snapshot tests found into their checks in the test
+/// file, telling on stderr what changed. Gives back how many tests couldn't be
+/// blessed, because they didn't run or their checks couldn't be written. The
+/// file is only written if anything changed.
+pub fn bless_file(path: &Path, groups: &[&GroupResult]) -> io::Result<usize> {
+    let original = fs::read_to_string(path)?;
+    let mut yaml = original.clone();
+    let mut failed = 0;
+    for group in groups {
+        for (name, result) in &group.tests {
+            let full_name = format!("{}/{name}", group.name);
+            match &result.result {
+                Ok(()) => eprintln!("{full_name}: already up to date"),
+                Err(RunError::RegistersFailed(failures, None)) => {
+                    match bless(&yaml, &group.name, name, failures) {
+                        Ok((blessed, missing)) => {
+                            yaml = blessed;
+                            for failure in failures {
+                                match missing.iter().find(|(m, _)| std::ptr::eq(*m, failure)) {
+                                    Some((_, why)) => {
+                                        eprintln!("{full_name}: couldn't write {failure}, {why}")
+                                    }
+                                    None => eprintln!("{full_name}: {}", written(failure)),
+                                }
+                            }
+                            if !missing.is_empty() {
+                                failed += 1;
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("{full_name}: not blessed, {e}");
+                            failed += 1;
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{full_name}: not blessed, {e}");
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if yaml != original {
+        fs::write(path, yaml)?;
+    }
+    Ok(failed)
+}
+
+fn written(failure: &DataFailure) -> String {
+    match failure {
+        DataFailure::Register(reg, expected, found) => {
+            format!("{reg} changed from {expected} to {found}")
+        }
+        DataFailure::Memory(addr, expected, found) => {
+            format!(
+                "m[0x{addr:X}] changed from {expected} to {}",
+                expected.like(&found.to_bytes())
+            )
+        }
+    }
+}
+
+/// Values found that couldn't be written, with why
+pub type Missing<'a> = Vec<(&'a DataFailure, String)>;
+
+/// Writes the values found in a test into its checks, leaving the rest of the
+/// file as it was. Also gives back the values that couldn't be written, with
+/// why. Fails if the test can't be edited at all, such as when it is written
+/// in flow style.
+pub fn bless<'a>(
+    yaml: &str,
+    group: &str,
+    name: &str,
+    failures: &'a [DataFailure],
+) -> Result<(String, Missing<'a>), String> {
+    let Some(span) = test_spans(yaml).remove(&(group.to_string(), name.to_string())) else {
+        return Err("the test isn't written in the file as a block of `key: value` lines".into());
+    };
+    let mut lines: Vec<String> = yaml.lines().map(ToString::to_string).collect();
+    if lines[span.start]
+        .trim_start()
+        .strip_prefix("- ")
+        .is_some_and(|item| is_flow(item.trim()))
+    {
+        return Err("the test is written in flow style, which can't be blessed".into());
+    }
+    // The keys of the test itself, and not those inside its values
+    let top = match entry_range(&lines[span.start]) {
+        Some((key_indent, _, _)) if lines[span.start].trim_start().starts_with("- ") => key_indent,
+        _ => (span.start + 1..span.end)
+            .find(|i| content(&lines[*i]).is_some())
+            .map_or(0, |i| indent(&lines[i])),
+    };
+    let keys: Vec<_> = span
+        .clone()
+        .filter_map(|i| {
+            let (key_indent, key, value) = entry(&lines[i])?;
+            (key_indent == top).then(|| (i, key.to_string(), value.to_string()))
+        })
+        .collect();
+    let checks = match keys.iter().find(|(_, key, _)| key == "checks") {
+        Some((_, _, value)) if !value.is_empty() => {
+            return Err("its checks are written in flow style, which can't be blessed".into());
+        }
+        Some((i, _, _)) => Some(i + 1..block_end(&lines, *i, top).min(span.end)),
+        // A test without settings is all checks
+        None if keys.iter().all(|(_, key, _)| is_check(key)) => Some(span),
+        // The checks come from its defaults or fixtures
+        None => None,
+    };
+    let Some(checks) = checks else {
+        let missing = failures.iter().map(|f| (f, NOT_IN_TEST.to_string()));
+        return Ok((yaml.to_string(), missing.collect()));
+    };
+
+    let mut missing = vec![];
+    for failure in failures {
+        let done = match failure {
+            DataFailure::Register(reg, _, found) => {
+                set_register(&mut lines, checks.clone(), reg.number(), *found)
+            }
+            DataFailure::Memory(addr, expected, found) => set_memory(
+                &mut lines,
+                checks.clone(),
+                *addr,
+                &expected.like(&found.to_bytes()),
+            ),
+        };
+        if let Err(why) = done {
+            missing.push((failure, why));
+        }
+    }
+    let mut blessed = lines.join("\n");
+    if yaml.ends_with('\n') {
+        blessed.push('\n');
+    }
+    Ok((blessed, missing))
+}
+
+/// Whether a key is a register or memory check
+fn is_check(key: &str) -> bool {
+    key.strip_prefix(['r', 'R'])
+        .is_some_and(|n| n.parse::<u8>().is_ok())
+        || key.starts_with(['m', 'M']) && key[1..].starts_with('(')
+}
+
+/// Whether a value is a map or list written in flow style
+fn is_flow(value: &str) -> bool {
+    value.starts_with(['{', '['])
+}
+
+/// Why a value of the checks can't be written
+const NOT_IN_TEST: &str = "its check isn't written in the test itself";
+
+fn set_register(
+    lines: &mut [String],
+    checks: Range<usize>,
+    number: u8,
+    value: u32,
+) -> Result<(), String> {
+    let (i, range) = checks
+        .into_iter()
+        .find_map(|i| {
+            let (_, key, range) = entry_range(&lines[i])?;
+            let n = key.strip_prefix(['r', 'R'])?.parse::<u8>().ok()?;
+            (n == number).then_some((i, range))
+        })
+        .ok_or(NOT_IN_TEST)?;
+    let value = if lines[i][range.clone()].starts_with("0x") {
+        format!("0x{value:X}")
+    } else {
+        value.to_string()
+    };
+    lines[i].replace_range(range, &value);
+    Ok(())
+}
+
+fn set_memory(
+    lines: &mut [String],
+    checks: Range<usize>,
+    addr: u32,
+    data: &MemoryData,
+) -> Result<(), String> {
+    let (m, key_indent, value) = checks
+        .into_iter()
+        .find_map(|i| {
+            let (key_indent, key, value) = entry(&lines[i])?;
+            let address = key
+                .strip_prefix(['m', 'M'])?
+                .strip_prefix('(')?
+                .strip_suffix(')')?;
+            let address = address
+                .strip_prefix("0x")
+                .map_or_else(|| address.parse().ok(), |h| u32::from_str_radix(h, 16).ok())?;
+            (address == addr).then_some((i, key_indent, value))
+        })
+        .ok_or(NOT_IN_TEST)?;
+    if !value.is_empty() {
+        return Err(format!(
+            "it is written in flow style, which can't be blessed: {value}"
+        ));
+    }
+    let (i, kind_indent, range) = (m + 1..block_end(lines, m, key_indent))
+        .find_map(|i| {
+            let (kind_indent, kind, range) = entry_range(&lines[i])?;
+            MEMORY_KINDS
+                .contains(&kind)
+                .then_some((i, kind_indent, range))
+        })
+        .ok_or(NOT_IN_TEST)?;
+    let hex = lines[i][range.clone()].contains("0x");
+    let (kind, value) = yaml_value(data, hex);
+    let line = &lines[i];
+    lines[i] = format!(
+        "{}{kind}: {value}{}",
+        &line[..kind_indent],
+        &line[range.end..]
+    );
+    Ok(())
+}
+
+/// The key and value of memory data as they are written in a test file
+fn yaml_value(data: &MemoryData, hex: bool) -> (&'static str, String) {
+    let number = |n: u64| {
+        if hex {
+            format!("0x{n:X}")
+        } else {
+            n.to_string()
+        }
+    };
+    match data {
+        MemoryData::Bytes(bytes) => (
+            "bytes",
+            format!(
+                "[{}]",
+                bytes
+                    .iter()
+                    .map(|b| format!("0x{b:02X}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ),
+        MemoryData::Byte(b) => ("byte", number(*b as u64)),
+        MemoryData::HalfWord(h) => ("half_word", number(*h as u64)),
+        MemoryData::Word(w) => ("word", number(*w as u64)),
+        MemoryData::DoubleWord(d) => ("double_word", number(*d)),
+        MemoryData::Text(s) => ("text", serde_json::to_string(s).unwrap()),
+    }
+}
+
+/// The indentation and key of a `key: value` line, which may be the first of a
+/// list item, and the value without its comment
+fn entry(line: &str) -> Option<(usize, &str, &str)> {
+    entry_range(line).map(|(key_indent, key, range)| (key_indent, key, &line[range]))
+}
+
+/// Like [`entry`], with where the value is in the line
+fn entry_range(line: &str) -> Option<(usize, &str, Range<usize>)> {
+    content(line)?;
+    let mut start = indent(line);
+    if let Some(item) = line[start..].strip_prefix("- ") {
+        start += 2 + indent(item);
+    }
+    let rest = &line[start..];
+    let colon = rest.char_indices().find_map(|(i, c)| {
+        (c == ':' && rest[i + 1..].chars().next().is_none_or(char::is_whitespace)).then_some(i)
+    })?;
+    let key = rest[..colon].trim().trim_matches(['"', '\'']);
+    let after = &rest[colon + 1..];
+    let value_start = start + colon + 1 + indent(after);
+    let value = strip_comment(&line[value_start..]).trim_end();
+    Some((start, key, value_start..value_start + value.len()))
+}
+
+/// Index of the first line after `start` that isn't inside the value of its key
+fn block_end(lines: &[String], start: usize, key_indent: usize) -> usize {
+    (start + 1..lines.len())
+        .find(|i| content(&lines[*i]).is_some() && indent(&lines[*i]) <= key_indent)
+        .unwrap_or(lines.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::GPRegister;
+
+    const YAML: &str = "\
+tests:
+  sum:
+    - name: small
+      setup:
+        r2: 1
+      checks:
+        r2: 0
+        r29: 0x10  # stays in hex
+        m(0x1000):
+          word: 7
+    - name: flow
+      checks: {r2: 1}
+    - {r2: 1}
+    - name: flow_memory
+      checks:
+        m(0x1000): {word: 7}
+    - name: from_fixture
+      use: base
+      setup:
+        r2: 1
+    - name: from_defaults
+      setup: {r2: 1}
+  single:
+    r3: 4
+";
+
+    fn register(number: u8, expected: u32, found: u32) -> DataFailure {
+        DataFailure::Register(GPRegister::new(number).unwrap(), expected, found)
+    }
+
+    #[test]
+    fn writes_the_values_found_into_the_checks() {
+        let failures = [
+            register(2, 0, 5),
+            register(29, 0x10, 0xFF),
+            DataFailure::Memory(0x1000, MemoryData::Word(7), MemoryData::Word(9)),
+        ];
+        let (blessed, missing) = bless(YAML, "sum", "small", &failures).unwrap();
+        assert!(missing.is_empty());
+        let expected = YAML
+            .replace("        r2: 0\n", "        r2: 5\n")
+            .replace("r29: 0x10  #", "r29: 0xFF  #")
+            .replacen("          word: 7\n", "          word: 9\n", 1);
+        assert_eq!(blessed, expected);
+
+        let failures = [register(3, 4, 8)];
+        let (blessed, missing) = bless(YAML, "single", "test_single", &failures).unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(blessed, YAML.replace("    r3: 4\n", "    r3: 8\n"));
+    }
+
+    #[test]
+    fn tells_what_it_couldnt_write() {
+        let failures = [register(2, 0, 5), register(5, 0, 1)];
+        let (blessed, missing) = bless(YAML, "sum", "small", &failures).unwrap();
+        assert_eq!(blessed, YAML.replace("        r2: 0\n", "        r2: 5\n"));
+        assert_eq!(missing.len(), 1);
+        assert!(std::ptr::eq(missing[0].0, &failures[1]));
+
+        let failures = [DataFailure::Memory(
+            0x1000,
+            MemoryData::Word(7),
+            MemoryData::Word(9),
+        )];
+        let (blessed, missing) = bless(YAML, "sum", "flow_memory", &failures).unwrap();
+        assert_eq!(blessed, YAML);
+        assert!(missing[0].1.contains("flow style"));
+    }
+
+    #[test]
+    fn leaves_the_setup_alone_when_the_checks_are_elsewhere() {
+        let failures = [register(2, 1, 5)];
+        for name in ["from_fixture", "from_defaults"] {
+            let (blessed, missing) = bless(YAML, "sum", name, &failures).unwrap();
+            assert_eq!(blessed, YAML, "{name}");
+            assert_eq!(missing[0].1, NOT_IN_TEST, "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_tests_it_cant_edit() {
+        let failures = [register(2, 1, 5)];
+        for name in ["flow", "test_sum2", "missing"] {
+            assert!(bless(YAML, "sum", name, &failures).is_err(), "{name}");
+        }
+        let flow = "tests:\n  sum: {r2: 1}\n";
+        assert!(bless(flow, "sum", "test_sum", &failures).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use serde_yaml::{Mapping, Value};
+
+use crate::locate::Step;
+
+/// Expands the tests of a group that have a `cases:` table into one test per
+/// row, before the group is read.
+///
+/// The rest of such a test is a template, where `{column}` is replaced with
+/// the value of the row: as is when it is the whole value, or as text inside
+/// longer strings and keys, such as `name: add_{a}_{b}` or `m({addr}):`. The
+/// rows are either a list of maps, or `columns:` with `rows:` of lists.
+/// Errors come with where the table is in the group.
+pub fn expand(group: &str, value: Value) -> Result<Value, (Vec<Step>, String)> {
+    let cases = Step::Key("cases".into());
+    match value {
+        Value::Sequence(tests) => expand_list(group, tests, &[]).map(Value::Sequence),
+        Value::Mapping(mut map) => {
+            if map.contains_key("cases") {
+                return expand_test(group, map)
+                    .map(Value::Sequence)
+                    .map_err(|e| (vec![cases], e));
+            }
+            if let Some(Value::Sequence(tests)) = map.remove("tests") {
+                let tests = expand_list(group, tests, &[Step::Key("tests".into())])?;
+                map.insert("tests".into(), Value::Sequence(tests));
+            }
+            Ok(Value::Mapping(map))
+        }
+        value => Ok(value),
+    }
+}
+
+/// The tests of a list, which is at `path` in the group
+fn expand_list(
+    group: &str,
+    tests: Vec<Value>,
+    path: &[Step],
+) -> Result<Vec<Value>, (Vec<Step>, String)> {
+    let mut res = Vec::with_capacity(tests.len());
+    for (index, test) in tests.into_iter().enumerate() {
+        match test {
+            Value::Mapping(map) if map.contains_key("cases") => {
+                res.extend(expand_test(group, map).map_err(|e| {
+                    let at = [Step::Index(index), Step::Key("cases".into())];
+                    ([path, &at].concat(), e)
+                })?)
+            }
+            test => res.push(test),
+        }
+    }
+    Ok(res)
+}
+
+fn expand_test(group: &str, mut template: Mapping) -> Result<Vec<Value>, String> {
+    let rows = rows(template.remove("cases").unwrap_or_default())?;
+    // Without an entrypoint a test runs the label named like it, which the
+    // names of the rows aren't
+    let base = match template.get("entrypoint") {
+        Some(Value::String(entrypoint)) => entrypoint.clone(),
+        _ => {
+            let base = format!("test_{group}");
+            template.insert("entrypoint".into(), base.clone().into());
+            base
+        }
+    };
+    if !template.contains_key("name") {
+        template.insert("name".into(), format!("{base}_{{case}}").into());
+    }
+    rows.into_iter()
+        .enumerate()
+        .map(|(index, mut row)| {
+            row.push(("case".to_string(), index.into()));
+            let mut test = Value::Mapping(template.clone());
+            fill(&mut test, &row);
+            match unfilled(&test, &row) {
+                Some(column) => Err(format!("case {index} has no value for {{{column}}}")),
+                None => Ok(test),
+            }
+        })
+        .collect()
+}
+
+/// The rows of a table, as the value of each column
+fn rows(cases: Value) -> Result<Vec<Vec<(String, Value)>>, String> {
+    let key = |k: Value| match k {
+        Value::String(s) => Ok(s),
+        k => Err(format!("column names have to be text, not {k:?}")),
+    };
+    match cases {
+        Value::Sequence(rows) => rows
+            .into_iter()
+            .map(|row| match row {
+                Value::Mapping(row) => row.into_iter().map(|(k, v)| Ok((key(k)?, v))).collect(),
+                _ => Err("each case has to be a map of its values".to_string()),
+            })
+            .collect(),
+        Value::Mapping(mut table) => {
+            let columns = match table.remove("columns") {
+                Some(Value::Sequence(columns)) => columns
+                    .into_iter()
+                    .map(key)
+                    .collect::<Result<Vec<_>, _>>()?,
+                _ => return Err("cases need `columns:` with `rows:`".to_string()),
+            };
+            let Some(Value::Sequence(rows)) = table.remove("rows") else {
+                return Err("cases need `rows:` with `columns:`".to_string());
+            };
+            rows.into_iter()
+                .enumerate()
+                .map(|(index, row)| match row {
+                    Value::Sequence(values) if values.len() == columns.len() => {
+                        Ok(columns.iter().cloned().zip(values).collect())
+                    }
+                    _ => Err(format!(
+                        "row {index} of the cases should have {} values",
+                        columns.len()
+                    )),
+                })
+                .collect()
+        }
+        _ => Err("cases have to be a list of rows".to_string()),
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n.to_string(),
+        Value::Bool(b) => b.to_string(),
+        value => serde_yaml::to_string(value)
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    }
+}
+
+fn replace(s: &str, row: &[(String, Value)]) -> String {
+    row.iter().fold(s.to_string(), |s, (column, value)| {
+        s.replace(&format!("{{{column}}}"), &text(value))
+    })
+}
+
+/// The column a value stands for, when it is all of it: `"{a}"`, or `{a}`
+/// which YAML reads as a map
+fn placeholder<'a>(value: &Value, row: &'a [(String, Value)]) -> Option<&'a Value> {
+    let column = match value {
+        Value::String(s) => s.strip_prefix('{')?.strip_suffix('}')?.to_string(),
+        Value::Mapping(map) if map.len() == 1 => match map.iter().next()? {
+            (Value::String(s), Value::Null) => s.clone(),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    row.iter().find(|(c, _)| *c == column).map(|(_, v)| v)
+}
+
+fn fill(value: &mut Value, row: &[(String, Value)]) {
+    if let Some(filled) = placeholder(value, row) {
+        *value = filled.clone();
+        return;
+    }
+    match value {
+        Value::String(s) => *s = replace(s, row),
+        Value::Sequence(items) => items.iter_mut().for_each(|item| fill(item, row)),
+        Value::Mapping(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(mut k, mut v)| {
+                    if let Value::String(s) = &mut k {
+                        *s = replace(s, row);
+                    }
+                    fill(&mut v, row);
+                    (k, v)
+                })
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+/// A `{column}` left in the test that the row has no value for
+fn unfilled(value: &Value, row: &[(String, Value)]) -> Option<String> {
+    match value {
+        Value::String(s) => s.split('{').skip(1).find_map(|after| {
+            let column = &after[..after.find('}')?];
+            (!column.is_empty()
+                && column
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !row.iter().any(|(c, _)| c == column))
+            .then(|| column.to_string())
+        }),
+        Value::Sequence(items) => items.iter().find_map(|item| unfilled(item, row)),
+        Value::Mapping(map) => map.iter().find_map(|(k, v)| {
+            if let Value::Mapping(single) = v {
+                if let Some((Value::String(column), Value::Null)) = single.iter().next() {
+                    if single.len() == 1 {
+                        return Some(column.clone());
+                    }
+                }
+            }
+            unfilled(k, row).or_else(|| unfilled(v, row))
+        }),
+        _ => None,
+    }
+}
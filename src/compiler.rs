@@ -1,24 +1,31 @@
 use std::{
+    collections::BTreeMap,
     env::current_dir,
     path::{Path, PathBuf},
     process::{Command, Output},
 };
 
+use crate::template::{self, DEFAULT_ASSEMBLER_CMD};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CompilerBuilder {
     assembler: PathBuf,
     ens_file: Option<PathBuf>,
     outfile: Option<PathBuf>,
     current_working_dir: Option<PathBuf>,
+    command: Vec<String>,
+    env: BTreeMap<String, String>,
 }
 
 impl CompilerBuilder {
-    pub const fn new(assembler: PathBuf) -> Self {
+    pub fn new(assembler: PathBuf) -> Self {
         Self {
             assembler,
             ens_file: None,
             outfile: None,
             current_working_dir: None,
+            command: template::default_command(DEFAULT_ASSEMBLER_CMD),
+            env: BTreeMap::new(),
         }
     }
 
@@ -45,7 +52,19 @@ impl CompilerBuilder {
         self
     }
 
-    pub fn build(&self) -> Compiler {
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn command(mut self, command: Vec<String>) -> Self {
+        self.command = command;
+        self
+    }
+
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn envs(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn build(&self) -> Compiler<'_> {
         // println!("A {}", self.assembler.display());
         // println!("E {:?}", self.ens_file);
         // println!("O {:?}", self.outfile);
@@ -59,6 +78,8 @@ impl CompilerBuilder {
                 .map(AsRef::as_ref)
                 .unwrap_or_else(|| Path::new(STD_OUTFILE)),
             current_dir: self.current_working_dir.as_ref().map(AsRef::as_ref),
+            command: &self.command,
+            env: &self.env,
         }
     }
 }
@@ -69,6 +90,8 @@ pub struct Compiler<'a> {
     ens_file: &'a Path,
     outfile: &'a Path,
     current_dir: Option<&'a Path>,
+    command: &'a [String],
+    env: &'a BTreeMap<String, String>,
 }
 
 pub const STD_OUTFILE: &str = "CDV.bin";
@@ -83,15 +106,32 @@ impl<'a> Compiler<'a> {
     // }
 
     pub fn run(&self, test_name: &str) -> std::io::Result<Output> {
-        let mut c = Command::new(current_dir().unwrap().join(self.assembler));
-        c.arg("-e")
-            .arg(test_name)
-            .arg("-o")
-            .arg(current_dir().unwrap().join(self.outfile))
-            .arg(current_dir().unwrap().join(self.ens_file));
+        self.command(test_name).output()
+    }
+
+    /// Assembler invocation for the given entrypoint, as done by [`Compiler::run`]
+    pub fn command(&self, test_name: &str) -> Command {
+        let cwd = current_dir().unwrap();
+        let (assembler, outfile, ens_file) = (
+            cwd.join(self.assembler),
+            cwd.join(self.outfile),
+            cwd.join(self.ens_file),
+        );
+        let args = template::expand(
+            self.command,
+            &[
+                ("assembler", assembler.as_os_str()),
+                ("entry", test_name.as_ref()),
+                ("out", outfile.as_os_str()),
+                ("src", ens_file.as_os_str()),
+            ],
+        );
+        let (program, args) = args.split_first().expect("assembler_cmd must not be empty");
+        let mut c = Command::new(program);
+        c.args(args).envs(self.env);
         if let Some(cwd) = self.current_dir {
             c.current_dir(cwd);
         }
-        c.output()
+        c
     }
 }
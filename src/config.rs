@@ -1,20 +1,313 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
 
-use crate::tests::Tests;
+use crate::{
+    loadable::{LoadError, Loadable},
+    locate::{self, Step},
+    tests::Tests,
+    validate::{self, Problem},
+};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Default, Deserialize, Clone)]
 pub struct Config {
     pub assembler: Option<PathBuf>,
     pub emulator: Option<PathBuf>,
     pub ens_file: Option<PathBuf>,
+    /// Known-good program the tests are also run with, to compare the results
+    pub reference_ens: Option<PathBuf>,
     pub serie_file: Option<PathBuf>,
+    /// Assembler invocation, with `{assembler}`, `{entry}`, `{out}` and `{src}` placeholders
+    pub assembler_cmd: Option<Vec<String>>,
+    /// Emulator invocation, with `{emulator}`, `{serie}` and `{bin}` placeholders
+    pub emulator_cmd: Option<Vec<String>>,
+    /// Extra environment variables for both the assembler and the emulator
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+impl Config {
+    /// Makes the paths relative to `dir`, the directory of the file the config
+    /// is in. Programs given by name only are left to be found in the `PATH`.
+    fn resolve(mut self, dir: &Path) -> Self {
+        for file in [
+            &mut self.ens_file,
+            &mut self.reference_ens,
+            &mut self.serie_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *file = dir.join(&*file);
+        }
+        for program in [&mut self.assembler, &mut self.emulator]
+            .into_iter()
+            .flatten()
+        {
+            if program.components().count() > 1 {
+                *program = dir.join(&*program);
+            }
+        }
+        self
+    }
+
+    /// Takes the settings `self` doesn't have from `other`
+    fn or(self, other: Self) -> Self {
+        let mut env = other.env;
+        env.extend(self.env);
+        Self {
+            assembler: self.assembler.or(other.assembler),
+            emulator: self.emulator.or(other.emulator),
+            ens_file: self.ens_file.or(other.ens_file),
+            reference_ens: self.reference_ens.or(other.reference_ens),
+            serie_file: self.serie_file.or(other.serie_file),
+            assembler_cmd: self.assembler_cmd.or(other.assembler_cmd),
+            emulator_cmd: self.emulator_cmd.or(other.emulator_cmd),
+            env,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ConfigAll {
     pub config: Config,
 
     pub tests: Tests,
+    /// The file each group is defined in
+    pub sources: HashMap<String, PathBuf>,
+    /// Every file the tests were read from
+    pub files: Vec<PathBuf>,
+}
+
+/// A test file as it is written, before the tests are put together
+#[derive(Deserialize)]
+struct TestFile {
+    #[serde(default)]
+    config: Config,
+    /// Other test files, or glob patterns of them, relative to this one
+    #[serde(default, deserialize_with = "one_or_many")]
+    include: Vec<String>,
+    /// Pieces of tests, by name, that tests can `use:`
+    #[serde(default)]
+    fixtures: Mapping,
+    #[serde(default)]
+    tests: HashMap<String, Value>,
+}
+
+/// A single file name, or a list of them
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        One(String),
+        Many(Vec<String>),
+    }
+    match Raw::deserialize(deserializer) {
+        Ok(Raw::One(name)) => Ok(vec![name]),
+        Ok(Raw::Many(names)) => Ok(names),
+        Err(_) => Err(serde::de::Error::custom(
+            "expected a file name or a list of them",
+        )),
+    }
+}
+
+/// The contents of a test file and the ones it includes
+#[derive(Default)]
+struct Suite {
+    config: Config,
+    fixtures: Mapping,
+    fixture_sources: HashMap<String, PathBuf>,
+    tests: HashMap<String, Value>,
+    sources: HashMap<String, PathBuf>,
+    files: Vec<PathBuf>,
+    /// What is wrong in the files, to tell all at once
+    problems: Vec<Problem>,
+}
+
+impl Suite {
+    /// Reads a test file and the ones it includes, except those in `seen`
+    fn load(path: &Path, seen: &mut HashSet<PathBuf>) -> Result<Self, LoadError> {
+        seen.insert(path.canonicalize()?);
+        let contents = fs::read_to_string(path)?;
+        let mut suite = Self::default();
+        let value = match serde_yaml::from_str(&contents) {
+            Ok(value) => value,
+            Err(e) => {
+                suite.problems.push(Problem::syntax(path, &e));
+                return Ok(suite);
+            }
+        };
+        suite.problems = validate::file(path, &contents, &value);
+        let file: TestFile = match serde_yaml::from_value(value) {
+            Ok(file) => file,
+            // The problems found already tell why
+            Err(_) if !suite.problems.is_empty() => return Ok(suite),
+            Err(e) => {
+                suite.problems.push(Problem::new(path, e.to_string()));
+                return Ok(suite);
+            }
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for pattern in &file.include {
+            for included in Self::find(dir, pattern, path)? {
+                match included.canonicalize() {
+                    Ok(canonical) if seen.contains(&canonical) => {}
+                    Ok(_) => suite.add(Self::load(&included, seen)?),
+                    Err(e) => suite.problems.push(Problem::new(
+                        path,
+                        format!("include {}: {e}", included.display()),
+                    )),
+                }
+            }
+        }
+        let own = Self {
+            config: file.config.resolve(dir),
+            fixture_sources: file
+                .fixtures
+                .keys()
+                .filter_map(Value::as_str)
+                .map(|name| (name.to_string(), path.to_path_buf()))
+                .collect(),
+            fixtures: file.fixtures,
+            sources: file
+                .tests
+                .keys()
+                .map(|group| (group.clone(), path.to_path_buf()))
+                .collect(),
+            tests: file.tests,
+            files: vec![path.to_path_buf()],
+            problems: vec![],
+        };
+        let config = std::mem::take(&mut suite.config);
+        suite.add(own);
+        // The file's own config goes before the ones it includes
+        suite.config = suite.config.or(config);
+        Ok(suite)
+    }
+
+    /// Every `*.tests.yml` file under `dir`, as if a file there included them
+    fn discover(dir: &Path) -> Result<Self, LoadError> {
+        let mut suite = Self::default();
+        let mut seen = HashSet::new();
+        for path in Self::find(dir, "**/*.tests.yml", dir)? {
+            if !seen.contains(&path.canonicalize()?) {
+                suite.add(Self::load(&path, &mut seen)?);
+            }
+        }
+        suite.files.push(dir.to_path_buf());
+        Ok(suite)
+    }
+
+    /// The files `pattern` names, relative to `dir`, sorted by path
+    fn find(dir: &Path, pattern: &str, from: &Path) -> Result<Vec<PathBuf>, LoadError> {
+        let path = dir.join(pattern);
+        if !pattern.contains(['*', '?', '[']) {
+            return Ok(vec![path]);
+        }
+        let invalid = |e: String| LoadError::Invalid(from.to_path_buf(), e);
+        let mut files = glob::glob(&path.to_string_lossy())
+            .map_err(|e| invalid(format!("include {pattern}: {e}")))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| invalid(format!("include {pattern}: {e}")))?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Adds the groups and fixtures of another file, which can't define the
+    /// same ones. Settings already set are kept.
+    fn add(&mut self, other: Self) {
+        self.problems.extend(other.problems);
+        for (group, source) in &other.sources {
+            if let Some(first) = self.sources.get(group) {
+                self.problems.push(Problem::new(
+                    source,
+                    format!("group {group} is already defined in {}", first.display()),
+                ));
+            }
+        }
+        for (fixture, source) in &other.fixture_sources {
+            if let Some(first) = self.fixture_sources.get(fixture) {
+                self.problems.push(Problem::new(
+                    source,
+                    format!(
+                        "fixture {fixture} is already defined in {}",
+                        first.display()
+                    ),
+                ));
+            }
+        }
+        self.config = std::mem::take(&mut self.config).or(other.config);
+        self.fixtures.extend(other.fixtures);
+        self.fixture_sources.extend(other.fixture_sources);
+        self.tests.extend(other.tests);
+        self.sources.extend(other.sources);
+        self.files.extend(other.files);
+    }
+}
+
+impl Loadable for ConfigAll {
+    /// Reads a test file along with the files it includes, or every
+    /// `*.tests.yml` file in a directory and the ones below it
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let suite = if path.is_dir() {
+            Suite::discover(path)?
+        } else {
+            Suite::load(path, &mut HashSet::new())?
+        };
+        if !suite.problems.is_empty() {
+            return Err(LoadError::Problems(suite.problems));
+        }
+        let tests = Tests::new(suite.tests, &suite.fixtures)
+            .map_err(|errors| LoadError::Problems(locate_errors(errors, &suite.sources)))?;
+        Ok(Self {
+            config: suite.config,
+            tests,
+            sources: suite.sources,
+            files: suite.files,
+        })
+    }
+}
+
+/// Problems at the groups the errors are in, in the files they are defined in
+pub fn locate_errors(
+    errors: Vec<(String, Vec<Step>, String)>,
+    sources: &HashMap<String, PathBuf>,
+) -> Vec<Problem> {
+    let mut contents = HashMap::new();
+    let mut problems: Vec<_> = errors
+        .into_iter()
+        .map(|(group, path, message)| {
+            let file = &sources[&group];
+            let yaml = contents
+                .entry(file)
+                .or_insert_with(|| fs::read_to_string(file).unwrap_or_default());
+            let steps = [vec![Step::Key("tests".into()), Step::Key(group)], path].concat();
+            Problem {
+                file: file.clone(),
+                position: Some(locate::position(yaml, &steps)),
+                message,
+            }
+        })
+        .collect();
+    problems.sort_by(|a, b| (&a.file, a.position).cmp(&(&b.file, b.position)));
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_takes_one_file_or_many() {
+        let one: TestFile = serde_yaml::from_str("include: common.yml").unwrap();
+        assert_eq!(one.include, ["common.yml"]);
+        let many: TestFile = serde_yaml::from_str("include: [a.yml, \"b/*.yml\"]").unwrap();
+        assert_eq!(many.include, ["a.yml", "b/*.yml"]);
+    }
 }
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::Debug,
     io::Write,
     iter::repeat,
@@ -8,15 +8,21 @@ use std::{
 };
 
 use encoding_rs::mem::decode_latin1;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{compiler::STD_OUTFILE, iter::IteratorExt};
+use crate::{
+    compiler::STD_OUTFILE,
+    iter::IteratorExt,
+    template::{self, DEFAULT_EMULATOR_CMD},
+};
 
 #[derive(Debug, Clone)]
 pub struct EmulatorBuilder {
     emu: PathBuf,
     serie: PathBuf,
     binfile: Option<PathBuf>,
+    command: Vec<String>,
+    env: BTreeMap<String, String>,
 }
 
 impl EmulatorBuilder {
@@ -28,6 +34,8 @@ impl EmulatorBuilder {
             emu: emu.as_ref().to_path_buf(),
             serie: serie.as_ref().to_path_buf(),
             binfile: None,
+            command: template::default_command(DEFAULT_EMULATOR_CMD),
+            env: BTreeMap::new(),
         }
     }
 
@@ -42,26 +50,51 @@ impl EmulatorBuilder {
         s
     }
 
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn command(mut self, command: Vec<String>) -> Self {
+        self.command = command;
+        self
+    }
+
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn envs(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
     pub fn build(&self) -> Emulator {
-        let mut cmd = Command::new(&self.emu);
-        cmd.arg("-c")
-            .arg(&self.serie)
-            .arg(
-                self.binfile
-                    .as_ref()
-                    .map(AsRef::as_ref)
-                    .unwrap_or_else(|| Path::new(STD_OUTFILE)),
-            )
+        let args = template::expand(
+            &self.command,
+            &[
+                ("emulator", self.emu.as_os_str()),
+                ("serie", self.serie.as_os_str()),
+                (
+                    "bin",
+                    self.binfile
+                        .as_ref()
+                        .map_or_else(|| Path::new(STD_OUTFILE), AsRef::as_ref)
+                        .as_os_str(),
+                ),
+            ],
+        );
+        let (program, args) = args.split_first().expect("emulator_cmd must not be empty");
+        let mut cmd = Command::new(program);
+        cmd.args(args)
+            .envs(&self.env)
             .stdin(Stdio::piped())
             .stderr(Stdio::piped())
             .stdout(Stdio::piped());
-        Emulator { command: cmd }
+        Emulator {
+            command: cmd,
+            transcript: None,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Emulator {
     command: Command,
+    transcript: Option<Transcript>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -72,6 +105,10 @@ impl GPRegister {
     pub fn new(n: u8) -> Option<Self> {
         (n < 32).then_some(Self(n))
     }
+
+    pub const fn number(self) -> u8 {
+        self.0
+    }
 }
 
 impl std::fmt::Debug for GPRegister {
@@ -94,6 +131,7 @@ pub enum Operation {
 pub enum EmulatorError {
     Failure(Output),
     IO(std::io::Error),
+    Parse(String),
 }
 
 impl From<std::io::Error> for EmulatorError {
@@ -115,49 +153,82 @@ impl Emulator {
         operations: &[Operation],
         memory_res: &[(u32, u32)],
     ) -> Result<RunResult, EmulatorError> {
-        let mut child = self.command.spawn()?;
-        let mut stdin = child.stdin.take().unwrap();
-        // let mut stdout = BufReader::new(emulated.stdout.take().unwrap());
+        let mut input = String::new();
         let mut op_skip = 0;
         // let mut set_mem = false;
         for op in operations {
             match op {
                 Operation::SetReg(GPRegister(n), val) => {
-                    writeln!(stdin, "r {n} 0x{val:x}")?;
+                    input += &format!("r {n} 0x{val:x}\n");
                     op_skip += 11;
                 }
                 Operation::SetMem(addr, data) => {
                     // set_mem = true;
                     for (i, word) in data.iter().enumerate() {
-                        writeln!(stdin, "I {} 0x{word:08x}", *addr + i as u32 * 4)?;
-                        stdin.flush()?;
+                        input += &format!("I {} 0x{word:08x}\n", *addr + i as u32 * 4);
                         op_skip += 1;
                     }
                 }
             }
-            stdin.flush()?;
         }
-        stdin.write_all(b"e\n")?;
-        stdin.flush()?;
+        input += "e\n";
         for (mem, len) in memory_res {
             let word_len = (len / 4) + u32::from(len % 4 > 0);
-            writeln!(stdin, "v {mem} {word_len}")?;
+            input += &format!("v {mem} {word_len}\n");
+        }
+        input += "q\n";
+        self.transcript = Some(Transcript {
+            stdin: input,
+            stdout: String::new(),
+            dump_start: op_skip + 12,
+        });
+
+        let mut child = self.command.spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        // let mut stdout = BufReader::new(emulated.stdout.take().unwrap());
+        for line in self.transcript.as_ref().unwrap().stdin.lines() {
+            writeln!(stdin, "{line}")?;
             stdin.flush()?;
         }
-        stdin.write_all(b"q\n")?;
-        stdin.flush()?;
         let output = child.wait_with_output()?;
+        let stdout = decode_latin1(&output.stdout);
+        if let Some(transcript) = &mut self.transcript {
+            transcript.stdout = stdout.to_string();
+        }
         if !output.status.success() {
             return Err(EmulatorError::Failure(output));
         }
-        let stdout = decode_latin1(&output.stdout);
         let lines = stdout.lines().skip(op_skip + 12);
         // let stop_code = lines.next().unwrap().trim();
-        Ok(RunResult::new(lines, memory_res))
+        RunResult::new(lines, memory_res).map_err(EmulatorError::Parse)
+    }
+
+    pub const fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Commands sent to and output received from the emulator in the last run
+    pub fn take_transcript(&mut self) -> Option<Transcript> {
+        self.transcript.take()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    pub stdin: String,
+    pub stdout: String,
+    /// Line of `stdout` where the stop condition and the dumps start
+    pub dump_start: usize,
+}
+
+impl Transcript {
+    /// Stop condition, register dump and memory dumps printed after the execution
+    pub fn dump(&self) -> impl Iterator<Item = &str> {
+        self.stdout.lines().skip(self.dump_start)
     }
 }
 
-#[derive(Clone, Eq, Deserialize)]
+#[derive(Clone, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MemoryData {
     Bytes(Vec<u8>),
@@ -182,6 +253,25 @@ impl PartialEq for MemoryData {
     }
 }
 
+impl std::fmt::Display for MemoryData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bytes(bytes) => {
+                write!(f, "[")?;
+                for (i, b) in bytes.iter().enumerate() {
+                    write!(f, "{}{b:02x}", if i == 0 { "" } else { " " })?;
+                }
+                write!(f, "]")
+            }
+            Self::Byte(b) => write!(f, "0x{b:02X}"),
+            Self::HalfWord(h) => write!(f, "0x{h:04X}"),
+            Self::Word(w) => write!(f, "0x{w:08X}"),
+            Self::DoubleWord(d) => write!(f, "0x{d:016X}"),
+            Self::Text(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+
 impl Debug for MemoryData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -262,6 +352,40 @@ impl MemoryData {
         }
     }
 
+    /// The same kind of data as `self`, read from `bytes` laid out in memory.
+    /// Text that doesn't fit in a string is given as bytes.
+    pub fn like(&self, bytes: &[u8]) -> Self {
+        let mut padded = bytes.to_vec();
+        padded.resize(padded.len().max(8), 0);
+        match self {
+            Self::Bytes(_) => Self::Bytes(bytes.to_vec()),
+            Self::Byte(_) => Self::Byte(padded[0]),
+            Self::HalfWord(_) => Self::HalfWord(u16::from_le_bytes([padded[0], padded[1]])),
+            Self::Word(_) => Self::Word(u32::from_le_bytes(padded[..4].try_into().unwrap())),
+            Self::DoubleWord(_) => {
+                Self::DoubleWord(u64::from_le_bytes(padded[..8].try_into().unwrap()))
+            }
+            Self::Text(_) => match bytes.iter().position(|b| *b == 0) {
+                Some(end) if bytes[..end].is_ascii() => {
+                    Self::Text(String::from_utf8_lossy(&bytes[..end]).into_owned())
+                }
+                _ => Self::Bytes(bytes.to_vec()),
+            },
+        }
+    }
+
+    /// Bytes as they are laid out in memory
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Bytes(b) => b.clone(),
+            Self::Byte(b) => vec![*b],
+            Self::HalfWord(h) => h.to_le_bytes().to_vec(),
+            Self::Word(w) => w.to_le_bytes().to_vec(),
+            Self::DoubleWord(d) => d.to_le_bytes().to_vec(),
+            Self::Text(s) => s.bytes().chain(std::iter::once(0)).collect(),
+        }
+    }
+
     pub fn len_real(&self) -> u32 {
         use MemoryData::*;
         match self {
@@ -307,6 +431,7 @@ impl MemoryData {
     }
 }
 
+#[derive(Debug)]
 pub struct RunResult {
     registers: [u32; 32],
     memory: HashMap<u32, MemoryData>,
@@ -314,19 +439,32 @@ pub struct RunResult {
 }
 
 impl RunResult {
-    fn new<'a, I: Iterator<Item = &'a str> + Clone>(mut lines: I, memory: &[(u32, u32)]) -> Self {
-        let stop_code = lines.next().unwrap().trim();
+    fn new<'a, I: Iterator<Item = &'a str> + Clone>(
+        mut lines: I,
+        memory: &[(u32, u32)],
+    ) -> Result<Self, String> {
+        let stop_code = lines
+            .next()
+            .ok_or("output ended before the stop condition")?
+            .trim();
         let lines = lines.skip(2); // Special registers
+        if lines.clone().take(8).count() < 8 {
+            return Err("output ended before the register dump".to_string());
+        }
         let mut registers = [0; 32];
         for (i, reg) in lines
             .clone()
             .take(8)
             .flat_map(|s| s.trim().split('h'))
-            .filter_map(|s| (!s.trim().is_empty()).then(|| &s.trim()[6..]))
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().get(6..).unwrap_or_default())
             .enumerate()
-            .map(|(i, reg)| (i + 1, u32::from_str_radix(reg, 16).unwrap()))
         {
-            registers[i] = reg;
+            let value = u32::from_str_radix(reg, 16)
+                .map_err(|_| format!("invalid value for register r{:02}: {reg:?}", i + 1))?;
+            *registers
+                .get_mut(i + 1)
+                .ok_or("more than 31 registers in the register dump")? = value;
         }
         let mut extra = lines.skip(8);
         let mut memory_res: HashMap<u32, MemoryData> = HashMap::with_capacity(memory.len());
@@ -340,35 +478,45 @@ impl RunResult {
             // let initial_word_addr = (addr / 4) * 4;
             let mut words = Vec::<u32>::with_capacity(words as usize);
             for i in 0..lines {
-                let line = extra.next().unwrap();
+                let line = extra
+                    .next()
+                    .ok_or_else(|| format!("output ended in the memory dump of 0x{addr:X}"))?;
                 let addr = addr + i * (16 - (addr % 16));
                 for shift in ((addr % 16) / 4)..4 {
                     let num_start = 17 + shift as usize * 13;
                     if line.len() <= num_start {
                         break;
                     }
-                    let num = u32::from_str_radix(&line[num_start..(num_start + 8)], 16)
-                        .unwrap()
+                    let num = line
+                        .get(num_start..(num_start + 8))
+                        .and_then(|n| u32::from_str_radix(n, 16).ok())
+                        .ok_or_else(|| format!("invalid memory dump line: {line:?}"))?
                         .to_be();
                     words.push(num);
                 }
                 // println!("{} >{} {len} {line:?}", addr - addr % 16, (addr % 16) / 4)
             }
+            let first = || -> Result<u32, String> {
+                words
+                    .first()
+                    .copied()
+                    .ok_or_else(|| format!("empty memory dump of 0x{addr:X}"))
+            };
             if *len == 1 {
                 memory_res.insert(
                     *addr,
-                    MemoryData::Byte(words[0].to_le_bytes()[*addr as usize % 4]),
+                    MemoryData::Byte(first()?.to_le_bytes()[*addr as usize % 4]),
                 );
             } else if addr % 2 == 0 && *len == 2 {
                 let bytes =
-                    &words[0].to_le_bytes()[(*addr as usize % 2)..((*addr as usize % 2) + 2)];
+                    &first()?.to_le_bytes()[(*addr as usize % 2)..((*addr as usize % 2) + 2)];
                 memory_res.insert(
                     *addr,
                     MemoryData::HalfWord(u16::from_le_bytes([bytes[0], bytes[1]])),
                 );
             } else if addr % 4 == 0 && *len == 4 {
-                memory_res.insert(*addr, MemoryData::Word(words[0]));
-            } else if addr % 8 == 0 && *len == 8 {
+                memory_res.insert(*addr, MemoryData::Word(first()?));
+            } else if addr % 8 == 0 && *len == 8 && words.len() >= 2 {
                 memory_res.insert(
                     *addr,
                     MemoryData::DoubleWord(words[0] as u64 + ((words[1] as u64) << 32)),
@@ -390,7 +538,7 @@ impl RunResult {
         // for (addr, k) in &memory_res {
         //     println!("{addr}: {k:?}")
         // }
-        Self {
+        Ok(Self {
             registers,
             memory: memory_res,
             stop_code: if stop_code == "Fin ejecución" {
@@ -398,13 +546,17 @@ impl RunResult {
             } else {
                 Some(stop_code.to_owned())
             },
-        }
+        })
     }
 
     pub const fn get_reg(&self, reg: &GPRegister) -> u32 {
         self.registers[reg.0 as usize]
     }
 
+    pub const fn registers(&self) -> &[u32; 32] {
+        &self.registers
+    }
+
     pub fn get_mem(&self, addr: u32) -> Option<&MemoryData> {
         self.memory.get(&addr)
     }
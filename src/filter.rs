@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use clap::Args;
+use regex::Regex;
+
+use crate::tests::{TestData, Tests};
+
+/// Which tests to run, chosen on the command line. A test has to pass every
+/// kind of filter given, and any of the values given for each kind.
+#[derive(Args, Debug, Clone, Default)]
+pub struct Filter {
+    /// Only run the tests whose group/name contains one of these
+    pub names: Vec<String>,
+    /// Only run the tests of these groups
+    #[clap(long)]
+    pub group: Vec<String>,
+    /// Only run the tests whose name or group/name matches, as a glob or as /regex/
+    #[clap(long)]
+    pub test: Vec<TestPattern>,
+    /// Only run the tests with one of these tags
+    #[clap(long)]
+    pub tag: Vec<String>,
+    /// Skip the tests with any of these tags
+    #[clap(long)]
+    pub exclude_tag: Vec<String>,
+}
+
+/// A glob, where `*` is any text and `?` any character, or a regex between slashes
+#[derive(Debug, Clone)]
+pub struct TestPattern(Regex);
+
+impl FromStr for TestPattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(regex) = s
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|r| !r.is_empty())
+        {
+            return Regex::new(regex).map(Self);
+        }
+        let mut regex = String::from("^");
+        for c in s.chars() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Regex::new(&regex).map(Self)
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, group: &str, name: &str, test: &TestData) -> bool {
+        let full_name = format!("{group}/{name}");
+        let tags = test.get_tags();
+        (self.names.is_empty() || self.names.iter().any(|n| full_name.contains(n.as_str())))
+            && (self.group.is_empty() || self.group.iter().any(|g| g == group))
+            && (self.test.is_empty()
+                || self
+                    .test
+                    .iter()
+                    .any(|TestPattern(p)| p.is_match(name) || p.is_match(&full_name)))
+            && (self.tag.is_empty() || self.tag.iter().any(|t| tags.contains(t)))
+            && !self.exclude_tag.iter().any(|t| tags.contains(t))
+    }
+
+    /// The groups with tests that pass the filter and those tests, all sorted by
+    /// name. If any of them is marked with `only`, the rest are left out.
+    pub fn select<'a>(&self, tests: &'a Tests) -> Vec<(String, Vec<(String, &'a TestData)>)> {
+        let only = tests.get_tests().any(|(group, mut tests)| {
+            tests.any(|(name, test)| test.is_only() && self.matches(&group, &name, test))
+        });
+        let mut groups = tests
+            .get_tests()
+            .map(|(group, tests)| {
+                let mut tests = tests
+                    .filter(|(name, test)| self.matches(&group, name, test))
+                    .filter(|(_, test)| !only || test.is_only())
+                    .collect::<Vec<_>>();
+                tests.sort_by(|(a, _), (b, _)| a.cmp(b));
+                (group, tests)
+            })
+            .filter(|(_, tests)| !tests.is_empty())
+            .collect::<Vec<_>>();
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        groups
+    }
+}
@@ -0,0 +1,141 @@
+use serde_yaml::{Mapping, Value};
+
+use crate::locate::Step;
+
+/// Gives the tests of a group the `defaults:` of the group and the fixtures
+/// they `use:`, before the group is read.
+///
+/// A test is made of its defaults, then of its fixtures in order, then of its
+/// own keys, each replacing what comes before. Maps such as `setup:` and
+/// `checks:` are merged by register or address instead of replaced, so a test
+/// can change a single value of a fixture.
+///
+/// Errors come with where they are in the group.
+pub fn apply(value: Value, fixtures: &Mapping) -> Result<Value, (Vec<Step>, String)> {
+    let at = |steps: &[Step]| {
+        let steps = steps.to_vec();
+        move |e: String| ([steps, vec![Step::Key("use".into())]].concat(), e)
+    };
+    match value {
+        Value::Sequence(tests) => tests
+            .into_iter()
+            .enumerate()
+            .map(|(i, test)| apply_test(test, None, fixtures).map_err(at(&[Step::Index(i)])))
+            .collect::<Result<_, _>>()
+            .map(Value::Sequence),
+        Value::Mapping(mut map) if map.contains_key("tests") => {
+            let defaults_at = |e| (vec![Step::Key("defaults".into())], e);
+            let defaults = match map.remove("defaults") {
+                Some(Value::Mapping(defaults)) => {
+                    Some(resolve(defaults, fixtures, &mut vec![]).map_err(defaults_at)?)
+                }
+                Some(_) => return Err(defaults_at("defaults have to be a map".to_string())),
+                None => None,
+            };
+            if let Some(Value::Sequence(tests)) = map.remove("tests") {
+                let tests = tests
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, test)| {
+                        apply_test(test, defaults.as_ref(), fixtures)
+                            .map_err(at(&[Step::Key("tests".into()), Step::Index(i)]))
+                    })
+                    .collect::<Result<_, _>>()?;
+                map.insert("tests".into(), Value::Sequence(tests));
+            }
+            Ok(Value::Mapping(map))
+        }
+        test => apply_test(test, None, fixtures).map_err(at(&[])),
+    }
+}
+
+/// A test with the `defaults:` of its group, already resolved, and its fixtures
+fn apply_test(
+    test: Value,
+    defaults: Option<&Mapping>,
+    fixtures: &Mapping,
+) -> Result<Value, String> {
+    let Value::Mapping(mut test) = test else {
+        return Ok(test);
+    };
+    if defaults.is_none() && !test.contains_key("use") {
+        return Ok(Value::Mapping(test));
+    }
+    // A test with only checks doesn't name them
+    if test.keys().all(is_check) {
+        test = Mapping::from_iter([("checks".into(), Value::Mapping(test))]);
+    }
+    let mut res = defaults.cloned().unwrap_or_default();
+    merge(&mut res, resolve(test, fixtures, &mut vec![])?);
+    Ok(Value::Mapping(res))
+}
+
+/// Whether a key is a register or memory check
+fn is_check(key: &Value) -> bool {
+    key.as_str().is_some_and(|k| {
+        k.strip_prefix(['r', 'R'])
+            .is_some_and(|n| n.parse::<u8>().is_ok())
+            || k.starts_with("m(")
+            || k.starts_with("M(")
+    })
+}
+
+/// The fixtures a map uses, merged with its own keys. `used` are the
+/// fixtures being resolved, to catch those that use themselves.
+fn resolve(
+    mut map: Mapping,
+    fixtures: &Mapping,
+    used: &mut Vec<String>,
+) -> Result<Mapping, String> {
+    let names = match map.remove("use") {
+        None => vec![],
+        Some(Value::String(name)) => vec![name],
+        Some(Value::Sequence(names)) => names
+            .into_iter()
+            .map(|name| match name {
+                Value::String(name) => Ok(name),
+                name => Err(format!("fixture names have to be text, not {name:?}")),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err("use: takes a fixture name or a list of them".to_string()),
+    };
+    let mut res = Mapping::new();
+    for name in names {
+        if used.contains(&name) {
+            return Err(format!("fixture {name} uses itself"));
+        }
+        let Some(fixture) = fixtures.get(name.as_str()) else {
+            let known = fixtures
+                .keys()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(format!("unknown fixture {name}, the fixtures are: {known}"));
+        };
+        let Value::Mapping(fixture) = fixture else {
+            return Err(format!("fixture {name} has to be a map"));
+        };
+        used.push(name);
+        let fixture = resolve(fixture.clone(), fixtures, used)?;
+        used.pop();
+        merge(&mut res, fixture);
+    }
+    merge(&mut res, map);
+    Ok(res)
+}
+
+/// Adds the keys of `over` to `base`, merging the maps both have one level deep
+fn merge(base: &mut Mapping, over: Mapping) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Mapping(base)), Value::Mapping(over)) => {
+                for (k, v) in over {
+                    base.insert(k, v);
+                }
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
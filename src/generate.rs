@@ -0,0 +1,805 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{de::Error, Deserialize, Serialize};
+
+use crate::{
+    emulator::{GPRegister, MemoryData},
+    tests::{try_parse_hex_or_dec, TestCheck, TestData},
+};
+
+/// Most runs spent shrinking a failing case
+pub const MAX_SHRINK_RUNS: usize = 200;
+
+/// Random inputs for a test, which is run with `count` cases generated from them
+#[derive(Debug, Clone, Deserialize)]
+pub struct Generate {
+    /// How many cases are run
+    #[serde(default = "default_count")]
+    pub count: usize,
+    /// Seed for the cases of this test, instead of the one of the run
+    pub seed: Option<u64>,
+    /// What each register (`rN`) or memory address (`m(address)`) is set to
+    #[serde(deserialize_with = "pairs")]
+    pub setup: Vec<(Target, Domain)>,
+    /// Expected register values, as expressions of the inputs. Without them,
+    /// the results are compared with the reference program.
+    #[serde(default, deserialize_with = "register_pairs")]
+    pub checks: Vec<(GPRegister, Expr)>,
+}
+
+const fn default_count() -> usize {
+    10
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Register(GPRegister),
+    Memory(u32),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(n) = s.strip_prefix(['r', 'R']) {
+            n.parse::<u8>()
+                .ok()
+                .and_then(GPRegister::new)
+                .map(Self::Register)
+                .ok_or_else(|| format!("unknown register {s}, expected r0-r31"))
+        } else {
+            s.strip_prefix(['m', 'M'])
+                .and_then(|m| m.strip_prefix('('))
+                .and_then(|m| m.strip_suffix(')'))
+                .and_then(try_parse_hex_or_dec)
+                .map(Self::Memory)
+                .ok_or_else(|| format!("unknown input {s}, expected rN or m(address)"))
+        }
+    }
+}
+
+/// What a generated input can be
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Domain {
+    /// A number between both, included
+    Range(i64, i64),
+    /// A random number of words, half words or bytes in memory
+    Array {
+        #[serde(deserialize_with = "length")]
+        len: (usize, usize),
+        range: (i64, i64),
+        #[serde(default)]
+        size: Size,
+    },
+    /// Null terminated text in memory, of the given characters
+    String {
+        #[serde(deserialize_with = "length")]
+        len: (usize, usize),
+        #[serde(default = "default_chars")]
+        chars: String,
+    },
+    /// The length of the array or string generated at an address
+    Len(#[serde(deserialize_with = "memory_target")] u32),
+    /// A fixed value, such as the address of an array
+    Value(i64),
+}
+
+fn default_chars() -> String {
+    ('a'..='z').chain('A'..='Z').chain('0'..='9').collect()
+}
+
+/// A length, or `[min, max]` to pick one between both
+fn length<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<(usize, usize), D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Exact(usize),
+        Between(usize, usize),
+    }
+    match Raw::deserialize(deserializer) {
+        Ok(Raw::Exact(len)) => Ok((len, len)),
+        Ok(Raw::Between(min, max)) => Ok((min, max)),
+        Err(_) => Err(D::Error::custom("expected a length or [min, max]")),
+    }
+}
+
+fn memory_target<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    match String::deserialize(deserializer)?.parse() {
+        Ok(Target::Memory(addr)) => Ok(addr),
+        Ok(Target::Register(_)) => Err(D::Error::custom("expected a memory address")),
+        Err(e) => Err(D::Error::custom(e)),
+    }
+}
+
+/// Size of the elements of an array
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Size {
+    Byte,
+    HalfWord,
+    #[default]
+    Word,
+}
+
+impl Size {
+    const fn bytes(self) -> usize {
+        match self {
+            Self::Byte => 1,
+            Self::HalfWord => 2,
+            Self::Word => 4,
+        }
+    }
+}
+
+/// A generated input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Array(Vec<i64>),
+    Text(String),
+    /// Length of the input at an address, worked out when the case is used
+    Len(u32),
+}
+
+/// One set of generated inputs, in the order of the `setup` of the generator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case(pub Vec<Value>);
+
+/// Which generated case a result comes from, to run it again
+#[derive(Debug, Clone, Serialize)]
+pub struct Generated {
+    pub seed: u64,
+    /// Index of the case, of `count`
+    pub case: usize,
+    pub count: usize,
+    /// How many times the failing case was made smaller
+    pub shrinks: usize,
+    /// The inputs of the case, as given by [`Generate::describe`]
+    pub inputs: Vec<String>,
+}
+
+/// SplitMix64, which is plenty for test inputs
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Between both, included
+    fn between(&mut self, low: i64, high: i64) -> i64 {
+        if high <= low {
+            return low;
+        }
+        let span = (high as i128 - low as i128 + 1) as u128;
+        (low as i128 + (self.next() as u128 % span) as i128) as i64
+    }
+}
+
+/// FNV-1a, so the seed of each case doesn't depend on the standard library
+fn hash(seed: u64, name: &str, index: usize) -> u64 {
+    seed.to_le_bytes()
+        .into_iter()
+        .chain(name.bytes())
+        .chain(index.to_le_bytes())
+        .fold(0xCBF2_9CE4_8422_2325, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+}
+
+/// The number of the range closest to 0, which cases shrink towards
+fn simplest(low: i64, high: i64) -> i64 {
+    0.clamp(low, high.max(low))
+}
+
+/// Smaller numbers to try instead of `value`
+fn shrink_int(value: i64, low: i64, high: i64) -> Vec<i64> {
+    let target = simplest(low, high);
+    let mut res = vec![];
+    if value != target {
+        res.push(target);
+        let half = value - (value - target) / 2;
+        if half != value && half != target {
+            res.push(half);
+        }
+        let step = value - (value - target).signum();
+        if step != target && step != half {
+            res.push(step);
+        }
+    }
+    res
+}
+
+impl Generate {
+    /// The inputs of case `index` of a test, the same for the same seed
+    pub fn case(&self, seed: u64, test: &str, index: usize) -> Case {
+        let mut rng = Rng(hash(seed, test, index));
+        Case(
+            self.setup
+                .iter()
+                .map(|(_, domain)| match domain {
+                    Domain::Range(low, high) => Value::Int(rng.between(*low, *high)),
+                    Domain::Array { len, range, .. } => {
+                        let n = rng.between(len.0 as i64, len.1 as i64) as usize;
+                        Value::Array((0..n).map(|_| rng.between(range.0, range.1)).collect())
+                    }
+                    Domain::String { len, chars } => {
+                        let chars = chars.chars().collect::<Vec<_>>();
+                        let n = rng.between(len.0 as i64, len.1 as i64) as usize;
+                        Value::Text(
+                            (0..n)
+                                .filter_map(|_| {
+                                    let i = rng.between(0, chars.len() as i64 - 1);
+                                    chars.get(i as usize)
+                                })
+                                .collect(),
+                        )
+                    }
+                    Domain::Len(addr) => Value::Len(*addr),
+                    Domain::Value(v) => Value::Int(*v),
+                })
+                .collect(),
+        )
+    }
+
+    /// Smaller versions of a case, simplest first
+    pub fn shrink(&self, case: &Case) -> Vec<Case> {
+        let mut res = vec![];
+        for (i, ((_, domain), value)) in self.setup.iter().zip(&case.0).enumerate() {
+            let mut with = |value: Value| {
+                let mut smaller = case.clone();
+                smaller.0[i] = value;
+                res.push(smaller);
+            };
+            match (domain, value) {
+                (Domain::Range(low, high), Value::Int(v)) => {
+                    for v in shrink_int(*v, *low, *high) {
+                        with(Value::Int(v));
+                    }
+                }
+                (Domain::Array { len, range, .. }, Value::Array(items)) => {
+                    if items.len() > len.0 {
+                        let half = (items.len() / 2).max(len.0);
+                        with(Value::Array(items[..half].to_vec()));
+                        for skip in 0..items.len() {
+                            let mut fewer = items.clone();
+                            fewer.remove(skip);
+                            with(Value::Array(fewer));
+                        }
+                    }
+                    for (j, item) in items.iter().enumerate() {
+                        for v in shrink_int(*item, range.0, range.1) {
+                            let mut simpler = items.clone();
+                            simpler[j] = v;
+                            with(Value::Array(simpler));
+                        }
+                    }
+                }
+                (Domain::String { len, chars }, Value::Text(text)) => {
+                    let count = text.chars().count();
+                    if count > len.0 {
+                        let half = (count / 2).max(len.0);
+                        with(Value::Text(text.chars().take(half).collect()));
+                        with(Value::Text(text.chars().take(count - 1).collect()));
+                    }
+                    if let Some(first) = chars.chars().next() {
+                        if let Some(j) = text.chars().position(|c| c != first) {
+                            with(Value::Text(
+                                text.chars()
+                                    .enumerate()
+                                    .map(|(k, c)| if k == j { first } else { c })
+                                    .collect(),
+                            ));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        res
+    }
+
+    /// The test run for a case: `test` with the inputs added to its setup and
+    /// the expected values to its checks
+    pub fn test_data(&self, test: &TestData, case: &Case) -> TestData {
+        let mut data = test.clone();
+        if let TestData::WithSetup {
+            setup,
+            checks,
+            generate,
+            ..
+        } = &mut data
+        {
+            *generate = None;
+            for ((target, domain), value) in self.setup.iter().zip(&case.0) {
+                match (target, self.int(case, value)) {
+                    (Target::Register(reg), Some(v)) => {
+                        setup.0.push(TestCheck::Register(*reg, v as u32))
+                    }
+                    (Target::Memory(addr), Some(v)) => setup
+                        .0
+                        .push(TestCheck::Memory(*addr, MemoryData::Word(v as u32))),
+                    (Target::Memory(addr), None) => setup
+                        .0
+                        .push(TestCheck::Memory(*addr, memory(domain, value))),
+                    (Target::Register(_), None) => {}
+                }
+            }
+            for (reg, expr) in &self.checks {
+                checks
+                    .0
+                    .push(TestCheck::Register(*reg, expr.eval(self, case) as u32));
+            }
+        }
+        data
+    }
+
+    /// Value of a numeric input
+    fn int(&self, case: &Case, value: &Value) -> Option<i64> {
+        match value {
+            Value::Int(v) => Some(*v),
+            Value::Len(addr) => Some(self.len(case, *addr)),
+            Value::Array(_) | Value::Text(_) => None,
+        }
+    }
+
+    /// Length of the array or string at an address, or 0 if there isn't one
+    fn len(&self, case: &Case, addr: u32) -> i64 {
+        self.memory(case, addr).map_or(0, |value| match value {
+            Value::Array(items) => items.len() as i64,
+            Value::Text(text) => text.chars().count() as i64,
+            _ => 0,
+        })
+    }
+
+    fn memory<'a>(&self, case: &'a Case, addr: u32) -> Option<&'a Value> {
+        self.setup
+            .iter()
+            .zip(&case.0)
+            .find(|((target, _), _)| *target == Target::Memory(addr))
+            .map(|(_, value)| value)
+    }
+
+    fn register(&self, case: &Case, number: u8) -> i64 {
+        self.setup
+            .iter()
+            .zip(&case.0)
+            .find(|((target, _), _)| matches!(target, Target::Register(r) if r.number() == number))
+            .and_then(|(_, value)| self.int(case, value))
+            .unwrap_or(0)
+    }
+
+    /// The inputs of a case, one per line, as they are written in a test file
+    pub fn describe(&self, case: &Case) -> Vec<String> {
+        self.setup
+            .iter()
+            .zip(&case.0)
+            .map(|((target, _), value)| {
+                let target = match target {
+                    Target::Register(reg) => reg.to_string(),
+                    Target::Memory(addr) => format!("m(0x{addr:X})"),
+                };
+                match value {
+                    Value::Int(v) => format!("{target}: {v}"),
+                    Value::Len(_) => format!("{target}: {}", self.int(case, value).unwrap()),
+                    Value::Array(items) => format!("{target}: {items:?}"),
+                    Value::Text(text) => format!("{target}: {text:?}"),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Arrays and strings as they are laid out in memory
+fn memory(domain: &Domain, value: &Value) -> MemoryData {
+    match (domain, value) {
+        (Domain::Array { size, .. }, Value::Array(items)) => MemoryData::Bytes(
+            items
+                .iter()
+                .flat_map(|v| v.to_le_bytes().into_iter().take(size.bytes()))
+                .collect(),
+        ),
+        (_, Value::Text(text)) => MemoryData::Text(text.clone()),
+        _ => MemoryData::Bytes(vec![]),
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// The checks of a generator, which can only be registers
+fn register_pairs<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(GPRegister, Expr)>, D::Error> {
+    pairs::<D, Target, Expr>(deserializer)?
+        .into_iter()
+        .map(|(target, expr)| match target {
+            Target::Register(reg) => Ok((reg, expr)),
+            Target::Memory(_) => Err(D::Error::custom(
+                "only registers can be checked with expressions",
+            )),
+        })
+        .collect()
+}
+
+/// Maps are read as lists of pairs, to keep the order they are written in
+fn pairs<'de, D, K, V>(deserializer: D) -> Result<Vec<(K, V)>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    K: Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    struct PairsVisitor<K, V>(std::marker::PhantomData<(K, V)>);
+    impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> serde::de::Visitor<'de> for PairsVisitor<K, V> {
+        type Value = Vec<(K, V)>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(formatter, "a map")
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut res = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some(entry) = map.next_entry()? {
+                res.push(entry);
+            }
+            Ok(res)
+        }
+    }
+    deserializer.deserialize_map(PairsVisitor(std::marker::PhantomData))
+}
+
+/// An integer expression of the inputs of a case, such as `r2 + r3 * 2` or
+/// `sum(m(0x1000)) / len(m(0x1000))`. Arithmetic wraps around, and dividing by
+/// zero gives zero.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(i64),
+    Register(u8),
+    /// `len`, `sum`, `min` or `max` of the array or string at an address
+    Function(Function, u32),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Function {
+    Len,
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    /// Operators by precedence, loosest first
+    const LEVELS: [&'static [(&'static str, Self)]; 6] = [
+        &[("|", Self::Or)],
+        &[("^", Self::Xor)],
+        &[("&", Self::And)],
+        &[("<<", Self::Shl), (">>", Self::Shr)],
+        &[("+", Self::Add), ("-", Self::Sub)],
+        &[("*", Self::Mul), ("/", Self::Div), ("%", Self::Rem)],
+    ];
+
+    fn apply(self, l: i64, r: i64) -> i64 {
+        match self {
+            Self::Or => l | r,
+            Self::Xor => l ^ r,
+            Self::And => l & r,
+            Self::Shl => l.wrapping_shl(r as u32),
+            Self::Shr => l.wrapping_shr(r as u32),
+            Self::Add => l.wrapping_add(r),
+            Self::Sub => l.wrapping_sub(r),
+            Self::Mul => l.wrapping_mul(r),
+            Self::Div => l.checked_div(r).unwrap_or(0),
+            Self::Rem => l.checked_rem(r).unwrap_or(0),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, generate: &Generate, case: &Case) -> i64 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Register(n) => generate.register(case, *n),
+            Self::Function(Function::Len, addr) => generate.len(case, *addr),
+            Self::Function(function, addr) => {
+                let items = match generate.memory(case, *addr) {
+                    Some(Value::Array(items)) => items.clone(),
+                    Some(Value::Text(text)) => text.bytes().map(i64::from).collect(),
+                    _ => vec![],
+                };
+                match function {
+                    Function::Sum => items.iter().fold(0, |a, b| a.wrapping_add(*b)),
+                    Function::Min => items.iter().copied().min().unwrap_or(0),
+                    _ => items.iter().copied().max().unwrap_or(0),
+                }
+            }
+            Self::Neg(e) => e.eval(generate, case).wrapping_neg(),
+            Self::Not(e) => !e.eval(generate, case),
+            Self::Binary(op, l, r) => op.apply(l.eval(generate, case), r.eval(generate, case)),
+        }
+    }
+}
+
+/// Recursive descent over the text of an expression
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected `{token}` at `{}`", self.rest.trim()))
+        }
+    }
+
+    fn word(&mut self) -> &'a str {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(self.rest.len());
+        let (word, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        word
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = Op::LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        'outer: loop {
+            for (token, op) in *ops {
+                if self.eat(token) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(*op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        let word = self.word();
+        let function = match word {
+            "len" => Some(Function::Len),
+            "sum" => Some(Function::Sum),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            _ => None,
+        };
+        if let Some(function) = function {
+            self.expect("(")?;
+            let target = self.word();
+            self.expect("(")?;
+            let addr = self.word();
+            self.expect(")")?;
+            self.expect(")")?;
+            return match format!("{target}({addr})").parse() {
+                Ok(Target::Memory(addr)) => Ok(Expr::Function(function, addr)),
+                _ => Err(format!("expected m(address) in {word}")),
+            };
+        }
+        if let Ok(Target::Register(reg)) = word.parse() {
+            return Ok(Expr::Register(reg.number()));
+        }
+        word.strip_prefix("0x")
+            .map_or_else(|| word.parse().ok(), |h| i64::from_str_radix(h, 16).ok())
+            .map(Expr::Number)
+            .ok_or_else(|| match word {
+                "" => format!("expected a value at `{}`", self.rest.trim()),
+                word => format!("unknown value `{word}`"),
+            })
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { rest: s };
+        let expr = parser.binary(0)?;
+        match parser.rest.trim() {
+            "" => Ok(expr),
+            rest => Err(format!("unexpected `{rest}`")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(i64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(n) => Ok(Self::Number(n)),
+            Raw::Text(text) => text
+                .parse()
+                .map_err(|e| D::Error::custom(format!("expression `{text}`: {e}"))),
+        }
+    }
+}
+
+impl Display for Generated {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "case {} of {} with seed {}, shrunk {} times",
+            self.case + 1,
+            self.count,
+            self.seed,
+            self.shrinks
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A generator of the inputs in `setup`, in the order of their keys
+    fn generate(setup: serde_json::Value) -> Generate {
+        serde_json::from_value(json!({ "setup": setup })).unwrap()
+    }
+
+    /// The value of an expression with r2 = 7, r3 = 3 and m(0x1000) = [1, 5, 2]
+    fn eval(expr: &str) -> i64 {
+        let generate = generate(json!({
+            "r2": { "value": 7 },
+            "r3": { "value": 3 },
+            "m(0x1000)": { "array": { "len": 3, "range": [1, 1] } }
+        }));
+        let case = Case(vec![
+            Value::Array(vec![1, 5, 2]),
+            Value::Int(7),
+            Value::Int(3),
+        ]);
+        expr.parse::<Expr>().unwrap().eval(&generate, &case)
+    }
+
+    #[test]
+    fn expressions_follow_precedence() {
+        assert_eq!(eval("r2 + r3 * 2"), 13);
+        assert_eq!(eval("(r2 + r3) * 2"), 20);
+        assert_eq!(eval("r2 - r3 - 1"), 3);
+        assert_eq!(eval("r2 / r3 % 2"), 0);
+        assert_eq!(eval("1 << 2 + 1"), 8);
+        assert_eq!(eval("1 | 6 ^ 3 & 6"), 5);
+        assert_eq!(eval("-r2 % 4"), -3);
+        assert_eq!(eval("~0x0"), -1);
+        assert_eq!(eval("r2 / 0 + r2 % 0"), 0);
+        assert_eq!(eval("sum(m(0x1000)) + len(M(4096)) * max(m(0x1000))"), 23);
+        assert_eq!(eval("min(m(0x1000)) + r31"), 1);
+    }
+
+    #[test]
+    fn bad_expressions_are_rejected() {
+        for expr in [
+            "",
+            "r2 +",
+            "r2 r3",
+            "(r2",
+            "r32",
+            "len(r2)",
+            "sum m(0x1000)",
+        ] {
+            assert!(expr.parse::<Expr>().is_err(), "{expr}");
+        }
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_cases() {
+        let generate = generate(json!({
+            "r2": { "range": [-1000, 1000] },
+            "m(0x1000)": { "array": { "len": [0, 8], "range": [0, 255] } },
+            "m(0x2000)": { "string": { "len": [1, 8] } }
+        }));
+        let cases = |seed| {
+            (0..10)
+                .map(|i| generate.case(seed, "g/t", i))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(cases(42), cases(42));
+        assert_ne!(cases(42), cases(43));
+        assert_ne!(generate.case(42, "g/t", 0), generate.case(42, "g/u", 0));
+    }
+
+    /// Shrinks a case while it still fails, as failing tests are
+    fn shrink_while(generate: &Generate, mut case: Case, fails: impl Fn(&Case) -> bool) -> Case {
+        while let Some(smaller) = generate.shrink(&case).into_iter().find(&fails) {
+            case = smaller;
+        }
+        case
+    }
+
+    #[test]
+    fn shrinking_reaches_the_smallest_failing_case() {
+        let generate = generate(json!({
+            "r2": { "range": [-100, 100] },
+            "m(0x1000)": { "array": { "len": [1, 10], "range": [5, 50] } },
+            "m(0x2000)": { "string": { "len": [0, 10], "chars": "ab" } }
+        }));
+        let case = Case(vec![
+            Value::Array(vec![40, 12, 33, 50, 9, 7]),
+            Value::Text("bbabba".to_string()),
+            Value::Int(-87),
+        ]);
+        let fails = |case: &Case| matches!(case.0[2], Value::Int(v) if v <= -10);
+        assert_eq!(
+            shrink_while(&generate, case.clone(), fails).0,
+            [
+                Value::Array(vec![5]),
+                Value::Text(String::new()),
+                Value::Int(-10),
+            ]
+        );
+        let fails = |case: &Case| matches!(&case.0[0], Value::Array(items) if items.len() >= 3);
+        assert_eq!(
+            shrink_while(&generate, case, fails).0,
+            [
+                Value::Array(vec![5, 5, 5]),
+                Value::Text(String::new()),
+                Value::Int(0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::HashMap, io};
+
+use serde::Serialize;
+
+use crate::{
+    runner::GroupResult,
+    tests::{TestData, Tests},
+};
+
+/// How many points each test is worth, from the `points:`, `weight:` and
+/// `all_or_nothing:` fields of the test file
+#[derive(Debug, Clone)]
+pub struct Scheme {
+    groups: HashMap<String, GroupScheme>,
+    graded: bool,
+}
+
+#[derive(Debug, Clone)]
+struct GroupScheme {
+    all_or_nothing: bool,
+    tests: HashMap<String, f64>,
+}
+
+impl Scheme {
+    /// A test is worth its `points`. If the group has `points` of its own, what
+    /// the tests don't take is split between the rest by their `weight`.
+    /// Otherwise a test without `points` is worth its `weight`, which defaults to 1.
+    pub fn new(tests: &Tests) -> Self {
+        let mut graded = false;
+        let groups = tests
+            .get_groups()
+            .map(|(name, group)| {
+                let tests = group.tests(name).collect::<Vec<_>>();
+                graded |= group.get_points().is_some()
+                    || group.is_all_or_nothing()
+                    || tests
+                        .iter()
+                        .any(|(_, t)| t.get_points().is_some() || t.get_weight().is_some());
+                let explicit: f64 = tests.iter().filter_map(|(_, t)| t.get_points()).sum();
+                let total_weight: f64 = tests
+                    .iter()
+                    .filter(|(_, t)| t.get_points().is_none())
+                    .map(|(_, t)| t.get_weight().unwrap_or(1.0))
+                    .sum();
+                let worth = tests
+                    .iter()
+                    .map(|(test_name, test)| {
+                        let weight = test.get_weight().unwrap_or(1.0);
+                        let points = match (test.get_points(), group.get_points()) {
+                            (Some(points), _) => points,
+                            (None, Some(_)) if total_weight == 0.0 => 0.0,
+                            (None, Some(group_points)) => {
+                                (group_points - explicit).max(0.0) * weight / total_weight
+                            }
+                            (None, None) => weight,
+                        };
+                        (test_name.clone(), points)
+                    })
+                    .collect();
+                (
+                    name.to_string(),
+                    GroupScheme {
+                        all_or_nothing: group.is_all_or_nothing(),
+                        tests: worth,
+                    },
+                )
+            })
+            .collect();
+        Self { groups, graded }
+    }
+
+    /// Points a test is worth, 1 if the scheme doesn't know it
+    fn worth(&self, group: &str, name: &str) -> f64 {
+        self.groups
+            .get(group)
+            .and_then(|g| g.tests.get(name))
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Whether the test file gives any points, in which case the score is shown
+    pub const fn is_graded(&self) -> bool {
+        self.graded
+    }
+}
+
+/// Points obtained in a run
+#[derive(Debug, Clone, Serialize)]
+pub struct Score {
+    pub score: f64,
+    pub max: f64,
+    pub groups: Vec<GroupScore>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupScore {
+    pub name: String,
+    pub score: f64,
+    pub max: f64,
+}
+
+impl Score {
+    pub fn new(scheme: &Scheme, results: &[GroupResult]) -> Self {
+        let groups: Vec<_> = results
+            .iter()
+            .map(|group| {
+                let group_scheme = scheme.groups.get(&group.name);
+                let worth = |name: &String| scheme.worth(&group.name, name);
+                // Skipped tests and the ones expected to fail don't count
+                let graded = group
+                    .tests
+                    .iter()
+                    .filter(|(_, r)| r.skipped.is_none() && r.xfail.is_none());
+                let max: f64 = graded.clone().map(|(name, _)| worth(name)).sum();
+                let all_or_nothing = group_scheme.is_some_and(|g| g.all_or_nothing);
+                let score = if all_or_nothing {
+                    if graded.clone().all(|(_, r)| r.result.is_ok()) {
+                        max
+                    } else {
+                        0.0
+                    }
+                } else {
+                    graded
+                        .filter(|(_, r)| r.result.is_ok())
+                        .map(|(name, _)| worth(name))
+                        .sum()
+                };
+                GroupScore {
+                    name: group.name.clone(),
+                    score: round(score),
+                    max: round(max),
+                }
+            })
+            .collect();
+        Self {
+            score: round(groups.iter().map(|g| g.score).sum()),
+            max: round(groups.iter().map(|g| g.max).sum()),
+            groups,
+        }
+    }
+
+    /// The score of a run where every test failed, such as one of a program
+    /// that couldn't be read, out of the same points as if it had run
+    pub fn failed(scheme: &Scheme, tests: &[(String, Vec<(String, &TestData)>)]) -> Self {
+        let groups: Vec<_> = tests
+            .iter()
+            .map(|(group, tests)| GroupScore {
+                name: group.clone(),
+                score: 0.0,
+                max: round(
+                    tests
+                        .iter()
+                        .filter(|(_, t)| t.get_skip().is_none() && t.get_xfail().is_none())
+                        .map(|(name, _)| scheme.worth(group, name))
+                        .sum(),
+                ),
+            })
+            .collect();
+        Self {
+            score: 0.0,
+            max: round(groups.iter().map(|g| g.max).sum()),
+            groups,
+        }
+    }
+
+    /// One row per group and a last one with the total
+    pub fn write_csv<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "group,score,max")?;
+        for group in &self.groups {
+            writeln!(
+                out,
+                "{},{},{}",
+                csv_field(&group.name),
+                group.score,
+                group.max
+            )?;
+        }
+        writeln!(out, "total,{},{}", self.score, self.max)
+    }
+}
+
+/// Rounds to two decimals, which is as precise as marks get
+fn round(points: f64) -> f64 {
+    // Adding 0 turns the -0 of empty sums into 0
+    (points * 100.0).round() / 100.0 + 0.0
+}
+
+/// Quotes a CSV field if it needs it
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
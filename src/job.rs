@@ -0,0 +1,256 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    compiler::CompilerBuilder,
+    emulator::EmulatorBuilder,
+    generate::{Generate, Generated, MAX_SHRINK_RUNS},
+    repro,
+    runner::{run_test, RunError, TestResult},
+    tests::TestData,
+    workdir::{sanitize, KeepArtifacts, WorkDir},
+};
+
+/// Everything a worker thread needs to run a test in its own directory
+#[derive(Debug, Clone)]
+pub struct TestEnv {
+    pub assembler: CompilerBuilder,
+    pub emulator: EmulatorBuilder,
+    pub work_dir: PathBuf,
+    pub repro_dir: Option<PathBuf>,
+    pub keep_artifacts: Option<KeepArtifacts>,
+    /// Contents of the known-good program the results are compared with
+    pub reference: Option<String>,
+    /// Seed of the generated inputs of the tests that don't have their own
+    pub seed: u64,
+}
+
+impl TestEnv {
+    /// The same environment, with the directories of a single submission
+    pub fn for_submission(&self, submission: &str) -> Self {
+        Self {
+            work_dir: self.work_dir.join(sanitize(submission)),
+            repro_dir: self
+                .repro_dir
+                .as_ref()
+                .map(|dir| dir.join(sanitize(submission))),
+            ..self.clone()
+        }
+    }
+
+    /// Assembles `ens_file_contents` and runs a test on it, then writes its
+    /// repro script if it failed and keeps or removes its directory
+    pub fn run(
+        &self,
+        ens_file_contents: &str,
+        group: &str,
+        name: &str,
+        test: &TestData,
+    ) -> TestResult {
+        if let Some(reason) = test.get_skip() {
+            return TestResult::skipped(reason.to_string());
+        }
+        if let Some(generate) = test.get_generate() {
+            return self.run_generated(ens_file_contents, group, name, test, generate);
+        }
+        self.run_once(ens_file_contents, group, name, test)
+    }
+
+    /// Runs the cases of a test with generated inputs until one fails, which
+    /// is then shrunk to the smallest inputs that still fail
+    fn run_generated(
+        &self,
+        ens_file_contents: &str,
+        group: &str,
+        name: &str,
+        test: &TestData,
+        generate: &Generate,
+    ) -> TestResult {
+        let seed = generate.seed.unwrap_or(self.seed);
+        let full_name = format!("{group}/{name}");
+        let mut last = None;
+        for index in 0..generate.count {
+            let mut case = generate.case(seed, &full_name, index);
+            let data = generate.test_data(test, &case);
+            let (mut path, mut result) = match self.attempt(ens_file_contents, group, name, &data) {
+                Ok(attempt) => attempt,
+                Err(e) => return TestResult::errored(e),
+            };
+            if result.result.is_ok() {
+                last = Some(self.finish(&path, ens_file_contents, group, name, &data, result));
+                continue;
+            }
+            // Only the smallest case found gets its repro script and artifacts
+            let (mut shrinks, mut runs) = (0, 0);
+            'shrink: while runs < MAX_SHRINK_RUNS {
+                for smaller in generate.shrink(&case) {
+                    runs += 1;
+                    let data = generate.test_data(test, &smaller);
+                    match self.attempt(ens_file_contents, group, name, &data) {
+                        Ok((smaller_path, smaller_result)) if smaller_result.result.is_err() => {
+                            let _ = fs::remove_dir_all(&path);
+                            (case, path, result) = (smaller, smaller_path, smaller_result);
+                            shrinks += 1;
+                            continue 'shrink;
+                        }
+                        Ok((smaller_path, _)) => {
+                            let _ = fs::remove_dir_all(&smaller_path);
+                        }
+                        // The files couldn't be written, which says nothing of the case
+                        Err(_) => {}
+                    }
+                    if runs >= MAX_SHRINK_RUNS {
+                        break;
+                    }
+                }
+                break;
+            }
+            let data = generate.test_data(test, &case);
+            let mut result = self.finish(&path, ens_file_contents, group, name, &data, result);
+            result.generated = Some(Generated {
+                seed,
+                case: index,
+                count: generate.count,
+                shrinks,
+                inputs: generate.describe(&case),
+            });
+            return result;
+        }
+        last.unwrap_or_else(|| TestResult::skipped("no cases to generate".to_string()))
+    }
+
+    /// Runs a test once, with the inputs it has
+    fn run_once(
+        &self,
+        ens_file_contents: &str,
+        group: &str,
+        name: &str,
+        test: &TestData,
+    ) -> TestResult {
+        match self.attempt(ens_file_contents, group, name, test) {
+            Ok((path, result)) => self.finish(&path, ens_file_contents, group, name, test, result),
+            Err(e) => TestResult::errored(e),
+        }
+    }
+
+    /// Runs a test in a new directory of its own, which is left as it is
+    fn attempt(
+        &self,
+        ens_file_contents: &str,
+        group: &str,
+        name: &str,
+        test: &TestData,
+    ) -> Result<(PathBuf, TestResult), RunError> {
+        let path =
+            WorkDir::create_test_dir(&self.work_dir, group, name).map_err(RunError::Files)?;
+        let bin_path = path.join("CDV.bin");
+        let ens_path = path.join("CDV.ens");
+        fs::write(&ens_path, ens_file_contents).map_err(RunError::Files)?;
+        let mut emulator = self.emulator.binfile(bin_path.clone()).build();
+        let builder = self
+            .assembler
+            .outfile(bin_path)
+            .ens_file(ens_path)
+            .current_dir(path.clone());
+        let assembler = builder.build();
+        let reference_paths = (path.join("REF.bin"), path.join("REF.ens"));
+        let reference_builder = match &self.reference {
+            Some(contents) => {
+                let (bin_path, ens_path) = reference_paths.clone();
+                fs::write(&ens_path, contents).map_err(RunError::Files)?;
+                Some(
+                    self.assembler
+                        .outfile(bin_path)
+                        .ens_file(ens_path)
+                        .current_dir(path.clone()),
+                )
+            }
+            None => None,
+        };
+        let reference_assembler = reference_builder.as_ref().map(CompilerBuilder::build);
+        let mut reference_emulator = reference_assembler
+            .as_ref()
+            .map(|_| self.emulator.binfile(reference_paths.0.clone()).build());
+        // println!("Running job {id}: {} {}", group, name);
+        let result = run_test(
+            &assembler,
+            &mut emulator,
+            reference_assembler
+                .as_ref()
+                .zip(reference_emulator.as_mut()),
+            name,
+            test,
+        );
+        Ok((path, result))
+    }
+
+    /// Writes the repro script of a test that failed, then keeps or removes
+    /// the directory it ran in. The test is an error if they can't be written.
+    fn finish(
+        &self,
+        path: &Path,
+        ens_file_contents: &str,
+        group: &str,
+        name: &str,
+        test: &TestData,
+        mut result: TestResult,
+    ) -> TestResult {
+        if let Err(e) = self.write_artifacts(path, ens_file_contents, group, name, test, &result) {
+            result.result = Err(RunError::Files(e));
+        }
+        result
+    }
+
+    fn write_artifacts(
+        &self,
+        path: &Path,
+        ens_file_contents: &str,
+        group: &str,
+        name: &str,
+        test: &TestData,
+        result: &TestResult,
+    ) -> io::Result<()> {
+        if let (Some(repro_dir), Err(_)) = (&self.repro_dir, &result.result) {
+            // Named like the working directory, which no other test of the run has
+            let dir = repro_dir.join(path.strip_prefix(&self.work_dir).unwrap());
+            fs::create_dir_all(&dir)?;
+            let (bin_path, ens_path) = (dir.join("CDV.bin"), dir.join("CDV.ens"));
+            fs::write(&ens_path, ens_file_contents)?;
+            let repro_assembler = self
+                .assembler
+                .outfile(bin_path.clone())
+                .ens_file(ens_path)
+                .current_dir(dir.clone());
+            let repro_emulator = self.emulator.binfile(bin_path).build();
+            repro::write_repro(
+                &dir,
+                &format!("{group}/{name}"),
+                &repro_assembler
+                    .build()
+                    .command(test.get_entrypoint().unwrap_or(name)),
+                result
+                    .transcript
+                    .as_ref()
+                    .map(|t| (repro_emulator.command(), t)),
+            )?;
+        }
+        match self.keep_artifacts {
+            Some(KeepArtifacts::All) => save_transcript(path, result),
+            Some(KeepArtifacts::Failed) if result.result.is_err() => save_transcript(path, result),
+            _ => {
+                let _ = fs::remove_dir_all(path);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn save_transcript(path: &Path, result: &TestResult) -> io::Result<()> {
+    if let Some(transcript) = &result.transcript {
+        fs::write(path.join("emulator.in"), &transcript.stdin)?;
+        fs::write(path.join("emulator.out"), &transcript.stdout)?;
+    }
+    Ok(())
+}
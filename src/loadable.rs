@@ -1,11 +1,33 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use serde::Deserialize;
 
+use crate::validate::Problem;
+
 #[derive(Debug)]
 pub enum LoadError {
     IOError(std::io::Error),
     YAMLError(serde_yaml::Error),
+    /// A test file that can't be put together with the rest
+    Invalid(PathBuf, String),
+    /// Everything wrong in the test files
+    Problems(Vec<Problem>),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IOError(e) => write!(f, "{e}"),
+            Self::YAMLError(e) => write!(f, "{e}"),
+            Self::Invalid(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::Problems(problems) => {
+                for (i, problem) in problems.iter().enumerate() {
+                    write!(f, "{}{problem}", if i == 0 { "" } else { "\n" })?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl From<std::io::Error> for LoadError {
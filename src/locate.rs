@@ -0,0 +1,194 @@
+use std::{collections::HashMap, ops::Range};
+
+/// Where each test is defined in a block style test file, as a range of line
+/// indices, keyed by group and test name.
+///
+/// A single test spans its whole group, and a test in a list spans from its `-`
+/// to the next one.
+pub fn test_spans(yaml: &str) -> HashMap<(String, String), Range<usize>> {
+    let lines: Vec<_> = yaml.lines().collect();
+    let mut spans = HashMap::new();
+    let Some(tests) = lines
+        .iter()
+        .position(|l| indent(l) == 0 && key(l) == Some("tests"))
+    else {
+        return spans;
+    };
+    let block = block_end(&lines, tests);
+    let Some(group_indent) =
+        (tests + 1..block).find_map(|i| content(lines[i]).map(|_| indent(lines[i])))
+    else {
+        return spans;
+    };
+    for g in tests + 1..block {
+        let line = lines[g];
+        if content(line).is_none() || indent(line) != group_indent {
+            continue;
+        }
+        let Some(group) = key(line) else { continue };
+        let group = unquote(group);
+        let end = block_end(&lines, g);
+        let items = match first_content(&lines, g + 1, end) {
+            Some(first) if is_item(lines[first]) => Some((first, end)),
+            Some(first) => {
+                // A group with settings has its tests in a `tests:` list
+                (first..end)
+                    .find(|i| {
+                        indent(lines[*i]) == indent(lines[first]) && key(lines[*i]) == Some("tests")
+                    })
+                    .and_then(|t| {
+                        let end = block_end(&lines, t);
+                        first_content(&lines, t + 1, end)
+                            .filter(|i| is_item(lines[*i]))
+                            .map(|first| (first, end))
+                    })
+            }
+            None => None,
+        };
+        let Some((first, end)) = items else {
+            spans.insert((group.to_string(), format!("test_{group}")), g..end);
+            continue;
+        };
+        let item_indent = indent(lines[first]);
+        let starts: Vec<_> = (first..end)
+            .filter(|i| is_item(lines[*i]) && indent(lines[*i]) == item_indent)
+            .collect();
+        for (index, start) in starts.iter().enumerate() {
+            let item_end = starts.get(index + 1).copied().unwrap_or(end);
+            let name = (*start..item_end)
+                .find_map(|i| {
+                    let line = lines[i];
+                    let line = if i == *start {
+                        line.get(item_indent + 2..).unwrap_or_default()
+                    } else {
+                        line
+                    };
+                    let (k, v) = line.trim().split_once(':')?;
+                    (k.trim() == "name" && (i == *start || indent(lines[i]) == item_indent + 2))
+                        .then(|| unquote(strip_comment(v).trim()).to_string())
+                })
+                .unwrap_or_else(|| format!("test_{group}{index}"));
+            spans.insert((group.to_string(), name), *start..item_end);
+        }
+    }
+    spans
+}
+
+pub fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// The line without indentation, unless it is blank or a comment
+pub fn content(line: &str) -> Option<&str> {
+    let trimmed = line.trim();
+    (!trimmed.is_empty() && !trimmed.starts_with('#')).then_some(trimmed)
+}
+
+/// The key of a `key:` line that opens a block, and only of those
+fn key(line: &str) -> Option<&str> {
+    let line = strip_comment(content(line)?).trim_end();
+    line.strip_suffix(':').filter(|k| !k.contains(": "))
+}
+
+fn is_item(line: &str) -> bool {
+    content(line).is_some_and(|c| c == "-" || c.starts_with("- "))
+}
+
+/// Removes a trailing `# comment`, which has to be preceded by a space
+pub fn strip_comment(value: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in value.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() => return &value[..i],
+            _ => {}
+        }
+        previous = c;
+    }
+    value
+}
+
+fn unquote(s: &str) -> &str {
+    s.trim_matches(['"', '\''])
+}
+
+/// Index of the first line after `start` that is less indented than it
+pub fn block_end(lines: &[&str], start: usize) -> usize {
+    let start_indent = indent(lines[start]);
+    (start + 1..lines.len())
+        .find(|i| content(lines[*i]).is_some() && indent(lines[*i]) <= start_indent)
+        .unwrap_or(lines.len())
+}
+
+fn first_content(lines: &[&str], start: usize, end: usize) -> Option<usize> {
+    (start..end).find(|i| content(lines[*i]).is_some())
+}
+
+/// A step into a YAML value: a key of a map or an item of a list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+/// Line and column, from 1, of the value `path` leads to in a block style
+/// file. Inside a value written in flow style it gives where that starts, and
+/// the start of the file when not even the first step can be found.
+pub fn position(yaml: &str, path: &[Step]) -> (usize, usize) {
+    let lines: Vec<_> = yaml.lines().collect();
+    let mut found = (0, 0);
+    // Lines of the current value, and the column its first line starts at,
+    // which is past the `- ` of a list item
+    let (mut range, mut first_col) = (0..lines.len(), 0);
+    for step in path {
+        let Some(first) = first_content(&lines, range.start, range.end) else {
+            break;
+        };
+        let col = if first == range.start {
+            first_col.max(indent(lines[first]))
+        } else {
+            indent(lines[first])
+        };
+        let at_col = |i: usize| {
+            let line = lines[i];
+            (content(line).is_some() && (i == first || indent(line) == col))
+                .then(|| strip_comment(line.get(col..).unwrap_or_default()).trim_end())
+        };
+        // The line of the step, and where its value starts if it is a block
+        let next = match step {
+            Step::Key(k) => (first..range.end).find_map(|i| {
+                let rest = at_col(i)?;
+                let (key, value) = rest.split_once(": ").unwrap_or((rest, ""));
+                let key = key.strip_suffix(':').unwrap_or(key);
+                (unquote(key.trim()) == k).then(|| (i, value.is_empty().then_some((i + 1, 0))))
+            }),
+            Step::Index(n) => (first..range.end)
+                .filter(|i| at_col(*i).is_some_and(|l| l == "-" || l.starts_with("- ")))
+                .nth(*n)
+                .map(|i| {
+                    let item = &lines[i][col + 1..];
+                    let start = match content(item) {
+                        None => Some((i + 1, 0)),
+                        Some(c) if c.starts_with(['[', '{']) => None,
+                        Some(_) => Some((i, col + 1 + indent(item))),
+                    };
+                    (i, start)
+                }),
+        };
+        let Some((line, start)) = next else {
+            break;
+        };
+        found = (line, col);
+        let Some((start, start_col)) = start else {
+            break;
+        };
+        let end = (line + 1..range.end)
+            .find(|i| content(lines[*i]).is_some() && indent(lines[*i]) <= col)
+            .unwrap_or(range.end);
+        range = start..end;
+        first_col = start_col;
+    }
+    (found.0 + 1, found.1 + 1)
+}
@@ -1,24 +1,58 @@
-use std::{fs, io::Write, num::NonZeroUsize, ops::Deref, path::PathBuf, process::Output};
+use std::{
+    fs,
+    io::Write,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
-use clap::Parser;
-use compiler::{Compiler, CompilerBuilder};
-use config::ConfigAll;
-use emulator::{Emulator, EmulatorBuilder, GPRegister, MemoryData, Operation};
-use loadable::Loadable;
-use termcolor::{BufferedStandardStream, Color, ColorSpec, WriteColor};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand};
+use compiler::CompilerBuilder;
+use config::{Config, ConfigAll};
+use emulator::EmulatorBuilder;
+use filter::Filter;
+use grading::{Scheme, Score};
+use job::TestEnv;
+use loadable::{LoadError, Loadable};
+use report::{EventFormat, Format, ReportTarget};
+use runner::GroupResult;
+use state::LastRun;
+use termcolor::BufferedStandardStream;
 use tests::TestData;
 use threadpool::{FinishStatus, ThreadPool, UpdatedStatus};
+use validate::Problem;
+use workdir::{KeepArtifacts, WorkDir};
 
+mod annotations;
+mod batch;
+mod bless;
+mod cases;
 mod compiler;
 mod config;
 mod emulator;
+mod filter;
+mod fixtures;
+mod generate;
+mod grading;
 mod iter;
+mod job;
 mod loadable;
+mod locate;
+mod report;
+mod repro;
+mod runner;
+mod schema;
+mod state;
+mod template;
 mod tests;
 mod threadpool;
+mod validate;
+mod watch;
+mod workdir;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about = "A test runner for the DATSI Motorola 88k emulator", long_about = None)]
+#[command(subcommand_precedence_over_arg = true)]
 struct Args {
     #[clap(default_value = "tests.yml")]
     #[clap(short)]
@@ -31,78 +65,391 @@ struct Args {
     emulator: Option<PathBuf>,
     #[clap(long)]
     serie_file: Option<PathBuf>,
+    /// Known-good program to compare the results of every test with
+    #[clap(long)]
+    reference_ens: Option<PathBuf>,
+    /// Seed of the generated inputs, to run the same cases again
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Directory in which the temporary working directory of the run is created
+    #[clap(long)]
+    work_dir: Option<PathBuf>,
+    /// Keep the assembled files and emulator transcripts of all tests, or only the failed ones
+    #[clap(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "all")]
+    keep_artifacts: Option<KeepArtifacts>,
+    /// Write a script reproducing each failing test into this directory
+    #[clap(long)]
+    repro_dir: Option<PathBuf>,
+    /// Show the emulator transcript under each failure
+    #[clap(short, long)]
+    verbose: bool,
+    /// Write a report file after the run, as kind=path (kinds: junit, html, score-csv, score-json)
+    #[clap(long)]
+    report: Vec<ReportTarget>,
+    /// Output format of the results
+    #[clap(long, value_enum, default_value_t)]
+    format: Format,
+    /// Stream an event for every test started and finished on stderr
+    #[clap(long, value_enum)]
+    events: Option<EventFormat>,
+    /// Print the names of the tests that would run, without running them
+    #[clap(long)]
+    list: bool,
+    /// Run the tests that failed in the last run before the rest
+    #[clap(long)]
+    failed_first: bool,
+    /// Only run the tests that failed in the last run, or all if none did
+    #[clap(long, conflicts_with = "failed_first")]
+    last_failed: bool,
+    /// Run the tests again every time the test files or the program changes
+    #[clap(long, conflicts_with_all = ["list", "last_failed"])]
+    watch: bool,
+    /// Run the tests marked with `snapshot: true` against a known-good program
+    /// and write the values it gives into their checks
+    #[clap(long, conflicts_with_all = ["list", "watch", "failed_first", "last_failed"])]
+    bless: bool,
+    #[command(flatten)]
+    filter: Filter,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the tests against every submission in a directory and write a gradebook
+    Batch {
+        /// Directory with a .ens file, or a folder with one, per submission
+        submissions: PathBuf,
+        /// CSV file with the results of every submission
+        #[clap(short, long, default_value = "gradebook.csv")]
+        output: PathBuf,
+    },
+    /// Print the JSON Schema of the test files, for editors to check them as they are written
+    Schema,
+    /// Check test files, and the tests annotated in their programs, without running anything
+    Validate {
+        /// Test files or directories, the one given with -c by default
+        files: Vec<PathBuf>,
+    },
 }
 
 fn main() {
     let args = Args::parse();
 
-    let conf = ConfigAll::load(args.config).expect("correct test file");
+    match &args.command {
+        Some(Command::Schema) => {
+            println!("{:#}", schema::schema());
+            return;
+        }
+        Some(Command::Validate { files }) => {
+            let files = if files.is_empty() {
+                std::slice::from_ref(&args.config)
+            } else {
+                files
+            };
+            let mut invalid = false;
+            for file in files {
+                match load(&args, file) {
+                    Ok(conf) => {
+                        let tests: usize = conf.tests.get_tests().map(|(_, t)| t.count()).sum();
+                        println!("{}: {tests} tests, all valid", file.display());
+                    }
+                    Err(e) => {
+                        eprintln!("{e}");
+                        invalid = true;
+                    }
+                }
+            }
+            std::process::exit(if invalid { 2 } else { 0 });
+        }
+        _ => {}
+    }
+
+    let conf = load(&args, &args.config).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(2)
+    });
     // dbg!(&conf);
+    if args.list {
+        for (group, tests) in args.filter.select(&conf.tests) {
+            for (name, test) in tests {
+                match test.get_tags() {
+                    [] => println!("{group}/{name}"),
+                    tags => println!("{group}/{name} [{}]", tags.join(", ")),
+                }
+            }
+        }
+        return;
+    }
+    // The results of a batch only go to the gradebook
+    if matches!(args.command, Some(Command::Batch { .. }))
+        && (args.format != Format::Human || !args.report.is_empty() || args.events.is_some())
+    {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--format, --report and --events can't be used with batch, which writes a gradebook",
+            )
+            .exit();
+    }
+    std::process::exit(run(&args, conf))
+}
+
+/// Runs the tests, or grades the submissions, in a working directory that is
+/// removed when this returns unless the artifacts are kept. Gives back the
+/// exit code.
+fn run(args: &Args, conf: ConfigAll) -> i32 {
+    let mut work_dir = WorkDir::new(args.work_dir.as_deref()).expect("create working directory");
+    work_dir.remove_on_interrupt().expect("set Ctrl-C handler");
+    let keep_artifacts = args.keep_artifacts;
+    let repro_dir = args.repro_dir.as_ref().map(|dir| {
+        fs::create_dir_all(dir).unwrap();
+        dir.canonicalize().unwrap()
+    });
+    if args.watch {
+        watch(args, work_dir.path(), repro_dir);
+    }
+    let (mut env, ens_file) = match test_env(args, conf.config, work_dir.path(), repro_dir) {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+    let scheme = Scheme::new(&conf.tests);
+
+    if let Some(Command::Batch {
+        submissions,
+        output,
+    }) = &args.command
+    {
+        let preferred = ens_file.as_ref().and_then(|f| f.file_name());
+        let submissions = match batch::find_submissions(submissions, preferred) {
+            Ok(submissions) => submissions,
+            Err(e) => {
+                eprintln!("{}: {e}", submissions.display());
+                return 2;
+            }
+        };
+        let tests = args.filter.select(&conf.tests);
+        let results = batch::run(&submissions, &tests, &env, worker_threads());
+        batch::write_gradebook(output, &results, &tests, &scheme).expect("write gradebook");
+        eprintln!(
+            "Graded {} submissions into {}",
+            results.len(),
+            output.display()
+        );
+        let any_failed = results
+            .iter()
+            .flat_map(|result| &result.groups)
+            .flat_map(|group| &group.tests)
+            .any(|(_, result)| result.is_failure());
+        if work_dir.keep_artifacts(keep_artifacts, any_failed) {
+            eprintln!("Artifacts kept in {}", work_dir.path().display());
+        }
+        return 0;
+    }
+
+    let Some(ens_file) = ens_file else {
+        eprintln!("{NO_PROGRAM}");
+        return 2;
+    };
+    let ens_file_contents = match fs::read_to_string(&ens_file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {e}", ens_file.display());
+            return 2;
+        }
+    };
+    let spinner = args.format == Format::Human && args.events.is_none();
+
+    if args.bless {
+        // The program given is the reference itself
+        env.reference = None;
+        let mut tests = args.filter.select(&conf.tests);
+        for (_, tests) in &mut tests {
+            tests.retain(|(_, test)| test.is_snapshot());
+        }
+        tests.retain(|(_, tests)| !tests.is_empty());
+        if tests.is_empty() {
+            eprintln!("No tests marked with `snapshot: true` to bless");
+        }
+        let results = run_tests(env, ens_file_contents, tests, spinner, None);
+        // Each group is written back to the file it came from
+        let mut files = conf.sources.values().collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+        let mut failed = 0;
+        for group in results
+            .iter()
+            .filter(|group| !conf.sources.contains_key(&group.name))
+        {
+            for (name, _) in &group.tests {
+                eprintln!(
+                    "{}/{name}: not blessed, it is annotated in the program instead of a test file",
+                    group.name
+                );
+                failed += 1;
+            }
+        }
+        for file in files {
+            let groups = results
+                .iter()
+                .filter(|group| conf.sources.get(&group.name) == Some(file))
+                .collect::<Vec<_>>();
+            if groups.is_empty() {
+                continue;
+            }
+            match bless::bless_file(file, &groups) {
+                Ok(file_failed) => failed += file_failed,
+                Err(e) => {
+                    eprintln!("{}: {e}", file.display());
+                    return 2;
+                }
+            }
+        }
+        return failed as i32;
+    }
+
+    let start = Instant::now();
+
+    let state_path = LastRun::path_for(&args.config);
+    let mut last_run = LastRun::load(&state_path);
+    let mut tests = args.filter.select(&conf.tests);
+    if args.failed_first || args.last_failed {
+        let all = tests.clone();
+        last_run.prioritize(&mut tests, args.last_failed);
+        if args.last_failed && tests.is_empty() {
+            eprintln!("No failed tests in the last run, running all of them");
+            tests = all;
+        }
+    }
+    let results = run_tests(env, ens_file_contents, tests, spinner, args.events);
+    let duration = start.elapsed();
+
+    let failed_tests = results
+        .iter()
+        .flat_map(|group| &group.tests)
+        .filter(|(_, result)| result.is_failure())
+        .count() as i32;
+    let score = Score::new(&scheme, &results);
+    let shown_score = scheme.is_graded().then_some(&score);
+    match args.format {
+        Format::Human => {
+            let mut stdout = BufferedStandardStream::stdout(termcolor::ColorChoice::Auto);
+            report::human::write(&mut stdout, &results, args.verbose, shown_score).unwrap();
+        }
+        Format::Json => {
+            report::json::write(&mut std::io::stdout(), &results, duration, shown_score).unwrap();
+        }
+        Format::Tap => report::tap::write(&mut std::io::stdout(), &results, shown_score).unwrap(),
+    }
+    if work_dir.keep_artifacts(keep_artifacts, failed_tests != 0) {
+        eprintln!("Artifacts kept in {}", work_dir.path().display());
+    }
+
+    let mut report_failed = false;
+    for report in &args.report {
+        if let Err(e) = report.write(&results, &conf.sources, &score) {
+            eprintln!("{}: {e}", report.path.display());
+            report_failed = true;
+        }
+    }
+
+    last_run.update(&results, &conf.tests);
+    if let Err(e) = last_run.save(&state_path) {
+        eprintln!("Couldn't save the results to {}: {e}", state_path.display());
+    }
+
+    if report_failed {
+        return 2;
+    }
+    failed_tests
+}
+
+const NO_PROGRAM: &str = "No program to test given, with -e or ens_file in the config";
+
+/// Builds what the tests run with from the arguments and the config, which
+/// take precedence in that order. Also gives the program to test, if known.
+fn test_env(
+    args: &Args,
+    config: Config,
+    work_dir: &Path,
+    repro_dir: Option<PathBuf>,
+) -> Result<(TestEnv, Option<PathBuf>), String> {
     let assembler = args
         .assembler
-        .or(conf.config.assembler)
-        .expect("assembler in args or config");
+        .clone()
+        .or(config.assembler)
+        .ok_or("No assembler given, with --assembler or assembler in the config")?;
     let emulator = args
         .emulator
-        .or(conf.config.emulator)
-        .expect("emulator in args or config");
-    let ens_file = args
-        .ens_file
-        .or(conf.config.ens_file)
-        .expect("ens_file in args or config");
-    let serie_file = args
-        .serie_file
-        .or(conf.config.serie_file)
-        .unwrap_or_else(|| {
-            emulator
-                .parent()
-                .expect("emulator must have parent if serie_file not specified")
-                .join("serie")
-        });
-
-    let ens_file_contents = fs::read_to_string(ens_file).unwrap();
-    let assembler_builder = CompilerBuilder::new(assembler);
-    let emulator_builder = EmulatorBuilder::new(&emulator, serie_file);
+        .clone()
+        .or(config.emulator)
+        .ok_or("No emulator given, with --emulator or emulator in the config")?;
+    let ens_file = args.ens_file.clone().or(config.ens_file);
+    let serie_file = match args.serie_file.clone().or(config.serie_file) {
+        Some(serie_file) => serie_file,
+        None => emulator
+            .parent()
+            .ok_or("No serie file given, with --serie-file or serie_file in the config")?
+            .join("serie"),
+    };
 
-    // let start = std::time::Instant::now();
-    let mut threadpool = {
-        // let (assembler_builder, emulator_builder) =
-        //     (assembler_builder.clone(), emulator_builder.clone());
-        ThreadPool::<(usize, String, String, _), _>::new(
-            move |(group_id, group, name, registers): (usize, String, String, TestData), id| {
-                let path: PathBuf = PathBuf::from("tmp").join(format!("{id}"));
-                fs::create_dir_all(&path).unwrap();
-                let bin_path = path.join("CDV.bin");
-                let ens_path = path.join("CDV.ens");
-                fs::write(&ens_path, ens_file_contents.clone()).unwrap();
-                let mut emulator = emulator_builder.binfile(bin_path.clone()).build();
-                let builder = assembler_builder
-                    .outfile(bin_path)
-                    .ens_file(ens_path)
-                    .current_dir(path);
-                let assembler = builder.build();
-                // println!("Running job {id}: {} {}", group, name);
-                let r = run_test(&assembler, &mut emulator, name.as_str(), &registers);
-                (group_id, group, name, r)
-            },
-            std::thread::available_parallelism()
-                .map(NonZeroUsize::get)
-                .unwrap_or(6)
-                - 2,
-        )
+    let mut assembler_builder = CompilerBuilder::new(assembler).envs(config.env.clone());
+    if let Some(cmd) = config.assembler_cmd {
+        assembler_builder = assembler_builder.command(cmd);
+    }
+    let mut emulator_builder = EmulatorBuilder::new(&emulator, serie_file).envs(config.env);
+    if let Some(cmd) = config.emulator_cmd {
+        emulator_builder = emulator_builder.command(cmd);
+    }
+    let reference = args
+        .reference_ens
+        .clone()
+        .or(config.reference_ens)
+        .map(|path| {
+            fs::read_to_string(&path).map_err(|e| format!("Couldn't read {}: {e}", path.display()))
+        })
+        .transpose()?;
+    let env = TestEnv {
+        assembler: assembler_builder,
+        emulator: emulator_builder,
+        work_dir: work_dir.to_path_buf(),
+        repro_dir,
+        keep_artifacts: args.keep_artifacts,
+        reference,
+        seed: args.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        }),
     };
+    Ok((env, ens_file))
+}
+
+/// Runs the tests on the thread pool, showing the progress with a spinner or
+/// as events, and gives back their results by group
+fn run_tests(
+    env: TestEnv,
+    ens_file_contents: String,
+    tests: Vec<(String, Vec<(String, &TestData)>)>,
+    spinner: bool,
+    events: Option<EventFormat>,
+) -> Vec<GroupResult> {
+    let mut threadpool = ThreadPool::<(usize, String, String, _), _>::new(
+        move |(group_id, group, name, registers): (usize, String, String, TestData), _id| {
+            let result = env.run(&ens_file_contents, &group, &name, &registers);
+            (group_id, group, name, result)
+        },
+        worker_threads(),
+    );
 
     // let assembler_builder = assembler_builder.ens_file(ens_file);
     // let mut i = 0;
-    let mut tests = conf.tests.get_tests().collect::<Vec<_>>();
     let groups = tests.len();
-    let mut failed_groups = Vec::with_capacity(groups);
-    tests.sort_by(|(a, _), (b, _)| a.cmp(b));
     for (i, (group, tests)) in tests.into_iter().enumerate() {
         // println!("G: {}", group);
-        let mut tests = tests.collect::<Vec<_>>();
-        failed_groups.push((group.clone(), tests.len(), vec![]));
-        tests.sort_by(|(a, _), (b, _)| a.cmp(b));
         for (test_name, registers) in tests {
             // println!("{i:02} {group} {test_name}");
             threadpool.send_data((i, group.clone(), test_name, registers.clone()));
@@ -116,7 +463,17 @@ fn main() {
     const SPINNER_CHARS: [char; 8] = ['⣾', '⣽', '⣻', '⢿', '⡿', '⣟', '⣯', '⣷'];
     let mut i = 0;
     while (threadpool.is_finished()) != FinishStatus::Finished && groups != 0 {
-        if let UpdatedStatus::Changed(data) = threadpool.update_status() {
+        let status = threadpool.update_status();
+        if let Some(EventFormat::Ndjson) = events {
+            for event in threadpool.take_events() {
+                report::json::write_event(&mut std::io::stderr(), &event).unwrap();
+            }
+        }
+        if !spinner {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            continue;
+        }
+        if let UpdatedStatus::Changed(data) = status {
             let mut s = data.join(", ");
             if let Some(size) = termsize::get() {
                 if s.len() + 13 > size.cols as usize {
@@ -134,7 +491,9 @@ fn main() {
         std::io::stdout().flush().unwrap();
         std::thread::sleep(std::time::Duration::from_millis(50));
     }
-    print!("            \r");
+    if spinner {
+        print!("            \r");
+    }
 
     let mut results = Vec::with_capacity(groups);
     let mut current = (None, vec![]);
@@ -146,7 +505,11 @@ fn main() {
             if group == res_g {
                 current.0 = Some((group_id, group))
             } else {
-                results.push((group_id, group, current.1));
+                results.push(GroupResult {
+                    id: group_id,
+                    name: group,
+                    tests: current.1,
+                });
                 current = (Some((res_group_id, res_g)), vec![]);
             }
         } else {
@@ -157,257 +520,107 @@ fn main() {
         // println!("{current:?}");
     }
     if let Some((group_id, group)) = current.0.take() {
-        results.push((group_id, group, current.1));
+        results.push(GroupResult {
+            id: group_id,
+            name: group,
+            tests: current.1,
+        });
     }
-    fs::remove_dir_all("tmp").unwrap();
     // let end = std::time::Instant::now();
 
     // println!("Results: {results:#?}");
     // println!("Time for multithreaded: {} ms", (end - start).as_millis());
+    results
+}
 
-    let mut stdout = BufferedStandardStream::stdout(termcolor::ColorChoice::Auto);
-    let normal_color_spec = ColorSpec::new();
-    let mut ok_color_spec = ColorSpec::new();
-    ok_color_spec.set_fg(Some(Color::Green)).set_bold(true);
-    let mut error_color_spec = ColorSpec::new();
-    error_color_spec.set_fg(Some(Color::Red)).set_bold(true);
-    let mut blue_color_spec = ColorSpec::new();
-    blue_color_spec.set_fg(Some(Color::Blue)).set_bold(true);
-
-    let mut bold_color_spec = ColorSpec::new();
-    bold_color_spec.set_bold(true);
-
-    // let start = std::time::Instant::now();
-    let mut failed_tests = 0;
-    let mut ok_tests = 0;
-    for (group_id, group, tests) in results {
-        stdout.set_color(&bold_color_spec).unwrap();
-        writeln!(stdout, "{group}").unwrap();
-        stdout.flush().unwrap();
-        let mut failed_in_group = vec![];
-        for (test_name, result) in tests {
-            stdout.set_color(&normal_color_spec).unwrap();
-            write!(stdout, "{test_name:>30} ").unwrap();
-            stdout.flush().unwrap();
-            match result {
-                Ok(()) => {
-                    ok_tests += 1;
-                    stdout.set_color(&ok_color_spec).unwrap();
-                    writeln!(stdout, "OK").unwrap();
-                    stdout.flush().unwrap();
-                }
-                Err(x) => {
-                    failed_in_group.push(test_name);
-                    failed_tests += 1;
-                    stdout.set_color(&error_color_spec).unwrap();
-                    writeln!(stdout, "ERROR").unwrap();
-                    stdout.flush().unwrap();
-                    stdout.set_color(&normal_color_spec).unwrap();
-                    match x {
-                        RunError::CompileExec(out) => {
-                            writeln!(stdout, "{:>20} compiling: {out}", "").unwrap()
-                        }
-                        RunError::RunExec(out) => {
-                            writeln!(stdout, "{:>20} running: {out}", "").unwrap()
-                        }
-                        // RunError::StopFailed(out) => {
-                        //     writeln!(stdout, "{:>20} didn't reach stop: {out}", "").unwrap()
-                        // }
-                        RunError::Compile(out) => {
-                            writeln!(stdout, "{:>20} compiling (OUTPUT):", "").unwrap();
-                            writeln!(stdout, "STDOUT:").unwrap();
-                            stdout.write_all(&out.stdout).unwrap();
-                            writeln!(stdout, "STDERR:").unwrap();
-                            stdout.write_all(&out.stdout).unwrap();
-                            writeln!(stdout).unwrap();
-                        }
-                        RunError::Run(out) => {
-                            writeln!(stdout, "{:>20} running (OUTPUT):", "").unwrap();
-                            writeln!(stdout, "STDOUT:").unwrap();
-                            stdout.write_all(&out.stdout).unwrap();
-                            writeln!(stdout, "STDERR:").unwrap();
-                            stdout.write_all(&out.stdout).unwrap();
-                            writeln!(stdout).unwrap();
-                        }
-                        RunError::RegistersFailed(failures, stop_code) => {
-                            if let Some(code) = stop_code {
-                                stdout.set_color(&normal_color_spec).unwrap();
-                                write!(stdout, " =+= Unexpected stop condition: ").unwrap();
-                                stdout.set_color(&error_color_spec).unwrap();
-                                write!(stdout, "{code}").unwrap();
-                                stdout.set_color(&normal_color_spec).unwrap();
-                                writeln!(stdout, " =+=").unwrap();
-                                stdout.flush().unwrap();
-                            }
-                            for failure in failures {
-                                let (name, expected, found) = match failure {
-                                    DataFailure::Register(a, b, c) => {
-                                        (format!("{a}"), format!("{b}"), format!("{c}"))
-                                    }
-                                    DataFailure::Memory(a, b, c) => {
-                                        (format!("m[0x{a:X}]"), format!("{b:?}"), format!("{c:?}"))
-                                    }
-                                };
-                                stdout.set_color(&normal_color_spec).unwrap();
-                                write!(stdout, " =+= ").unwrap();
-                                stdout.set_color(&blue_color_spec).unwrap();
-                                write!(stdout, "{name}").unwrap();
-                                stdout.set_color(&normal_color_spec).unwrap();
-                                write!(stdout, " was ").unwrap();
-                                stdout.set_color(&error_color_spec).unwrap();
-                                write!(stdout, "{found}").unwrap();
-                                stdout.set_color(&normal_color_spec).unwrap();
-                                write!(stdout, ", but ").unwrap();
-                                stdout.set_color(&blue_color_spec).unwrap();
-                                write!(stdout, "{expected}").unwrap();
-                                stdout.set_color(&normal_color_spec).unwrap();
-                                writeln!(stdout, " was expected =+=").unwrap();
-                                stdout.flush().unwrap();
-                            }
-                            writeln!(stdout).unwrap();
-                            stdout.flush().unwrap();
-                        }
-                    }
-                    stdout.flush().unwrap();
+/// Reads the test files along with the tests annotated in the program, which
+/// is `ens_file` or the one of the config
+fn load(args: &Args, config: &Path) -> Result<ConfigAll, LoadError> {
+    let mut conf = ConfigAll::load(config)?;
+    let ens_file = args.ens_file.as_deref().or(conf.config.ens_file.as_deref());
+    if let Some(ens_file) = ens_file {
+        annotations::add(&mut conf.tests, ens_file)
+            .map_err(|e| LoadError::Invalid(ens_file.to_path_buf(), e))?;
+    }
+    if args.reference_ens.is_none() && conf.config.reference_ens.is_none() {
+        // Generated inputs without checks are only compared with the reference
+        let (mut located, mut problems) = (vec![], vec![]);
+        for (group, test) in conf.tests.get_groups() {
+            for (name, _) in test
+                .tests(group)
+                .filter(|(_, test)| test.get_generate().is_some() && !test.has_checks())
+            {
+                let message = format!(
+                    "{name} generates inputs without checks, and there is no reference_ens to compare with"
+                );
+                match (conf.sources.contains_key(group), ens_file) {
+                    (true, _) => located.push((group.to_string(), vec![], message)),
+                    (false, Some(ens_file)) => problems.push(Problem::new(ens_file, message)),
+                    (false, None) => problems.push(Problem::new(config, message)),
                 }
             }
         }
-        failed_groups[group_id].2 = failed_in_group;
-    }
-
-    // let end = std::time::Instant::now();
-
-    // println!("Results: {results:#?}");
-    // println!("Time for multithreaded: {} ms", (end - start).as_millis());
-    stdout.set_color(&normal_color_spec).unwrap();
-    for (group, total, failed) in failed_groups.iter().filter(|(_, _, f)| !f.is_empty()) {
-        writeln!(stdout).unwrap();
-        writeln!(
-            stdout,
-            "     {group} has failed tests: {}/{total}",
-            failed.len()
-        )
-        .unwrap();
-        for test in failed {
-            writeln!(stdout, "       {test} failed").unwrap();
+        problems.extend(config::locate_errors(located, &conf.sources));
+        if !problems.is_empty() {
+            return Err(LoadError::Problems(problems));
         }
     }
-    stdout.flush().unwrap();
-    stdout.set_color(&bold_color_spec).unwrap();
-    writeln!(stdout).unwrap();
-    writeln!(stdout, "{failed_tests:>6} tests failed").unwrap();
-    writeln!(stdout, "{ok_tests:>6} tests passed").unwrap();
-    stdout.flush().unwrap();
-
-    std::process::exit(failed_tests)
-}
-
-#[derive(Debug, Clone, PartialEq)]
-enum DataFailure {
-    Register(GPRegister, u32, u32),
-    Memory(u32, MemoryData, MemoryData),
-    // StopFailed(String),
-}
-
-#[derive(Debug)]
-enum RunError {
-    CompileExec(std::io::Error),
-    Compile(Output),
-    RunExec(std::io::Error),
-    Run(Output),
-    RegistersFailed(Vec<DataFailure>, Option<String>),
+    Ok(conf)
 }
 
-fn run_test(
-    assembler: &Compiler,
-    emulator: &mut Emulator,
-    test_name: &str,
-    registers: &TestData,
-) -> Result<(), RunError> {
-    let mut error_mem = Vec::new();
-    let (entrypoint, registers, operations) = match registers {
-        TestData::NoSetup(checks) => (None, checks, vec![]),
-        TestData::WithSetup {
-            name: _,
-            entrypoint,
-            setup,
-            checks,
-        } => (
-            entrypoint.clone(),
-            checks,
-            setup
-                .iter()
-                .filter_map(|x| match x {
-                    tests::TestCheck::Register(reg, val) => Some(Operation::SetReg(*reg, *val)),
-                    tests::TestCheck::Memory(addr, data) => {
-                        if addr % 4 == 0 {
-                            let r = data.words().ok().map(|w| Operation::SetMem(*addr, w));
-                            if r.is_none() {
-                                error_mem.push(*addr);
+/// Reruns the tests, the ones that failed first, every time the test files or
+/// the program changes, showing a short summary each time
+fn watch(args: &Args, work_dir: &Path, repro_dir: Option<PathBuf>) -> ! {
+    let state_path = LastRun::path_for(&args.config);
+    let mut last_run = LastRun::load(&state_path);
+    // The files included by the test file, as of the last time it could be read
+    let mut files = vec![args.config.clone()];
+    loop {
+        let mut watched = files.clone();
+        // Clear the screen
+        print!("\x1b[2J\x1b[H");
+        match load(args, &args.config) {
+            Err(e) => println!("Couldn't load {}: {e}", args.config.display()),
+            Ok(conf) => {
+                files.clone_from(&conf.files);
+                watched.clone_from(&conf.files);
+                match test_env(args, conf.config, work_dir, repro_dir.clone()) {
+                    Err(e) => println!("{e}"),
+                    Ok((_, None)) => println!("{NO_PROGRAM}"),
+                    Ok((env, Some(ens_file))) => {
+                        match fs::read_to_string(&ens_file) {
+                            Err(e) => println!("Couldn't read {}: {e}", ens_file.display()),
+                            Ok(contents) => {
+                                let mut tests = args.filter.select(&conf.tests);
+                                last_run.prioritize(&mut tests, false);
+                                let results = run_tests(env, contents, tests, true, None);
+                                let mut stdout =
+                                    BufferedStandardStream::stdout(termcolor::ColorChoice::Auto);
+                                report::human::write_summary(&mut stdout, &results).unwrap();
+                                last_run.update(&results, &conf.tests);
+                                if let Err(e) = last_run.save(&state_path) {
+                                    eprintln!(
+                                        "Couldn't save the results to {}: {e}",
+                                        state_path.display()
+                                    );
+                                }
                             }
-                            r
-                        } else {
-                            error_mem.push(*addr);
-                            None
                         }
+                        watched.push(ens_file);
                     }
-                })
-                .collect(),
-        ),
-    };
-    if !error_mem.is_empty() {
-        eprintln!(
-            "Memory setup can only be done at word level, error for addresses: {error_mem:?}"
-        );
-    }
-    let assembled = assembler
-        .run(entrypoint.as_deref().unwrap_or(test_name))
-        .map_err(RunError::CompileExec)?;
-    if !assembled.status.success() {
-        return Err(RunError::Compile(assembled));
-    }
-    // println!("\tCompile OK");
-    let memory_tests = registers
-        .iter()
-        .filter_map(|c| match c {
-            tests::TestCheck::Register(_, _) => None,
-            tests::TestCheck::Memory(addr, data) => Some((*addr, data.len_real())),
-        })
-        .collect::<Vec<_>>();
-
-    let run_res = emulator
-        .run(&operations, &memory_tests)
-        .map_err(|e| match e {
-            emulator::EmulatorError::Failure(e) => RunError::Run(e),
-            emulator::EmulatorError::IO(e) => RunError::RunExec(e),
-            // emulator::EmulatorError::Unfinished(e) => RunError::StopFailed(e),
-        })?;
-    // println!("{r1:?}");
-    let mut res = vec![];
-    for check in registers.deref() {
-        match check {
-            tests::TestCheck::Register(register, val) => {
-                let val = *val;
-                let found = run_res.get_reg(register);
-                if val != found {
-                    res.push(DataFailure::Register(*register, val, found));
-                }
-            }
-            tests::TestCheck::Memory(addr, data) => {
-                let val = data.clone();
-                let found = run_res.get_mem(*addr).unwrap().clone();
-                if val != found {
-                    res.push(DataFailure::Memory(*addr, val, found));
                 }
             }
         }
+        println!("Watching for changes, press Ctrl-C to stop");
+        watch::wait_for_change(&watched);
     }
-    if res.is_empty() {
-        Ok(())
-    } else {
-        Err(RunError::RegistersFailed(
-            res,
-            run_res.get_stop_code().map(ToString::to_string),
-        ))
-    }
+}
+
+/// Leaves two CPUs for the emulators' own work and for the main thread
+fn worker_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(6)
+        .saturating_sub(2)
+        .max(1)
 }
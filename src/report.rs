@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use clap::ValueEnum;
+use termcolor::{Color, ColorSpec};
+
+use crate::{emulator::Transcript, grading::Score, runner::GroupResult};
+
+pub mod html;
+pub mod human;
+pub mod json;
+pub mod junit;
+pub mod tap;
+
+/// How the results are printed on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    /// Coloured report for the terminal
+    #[default]
+    Human,
+    /// A single JSON document
+    Json,
+    /// Test Anything Protocol, version 13
+    Tap,
+}
+
+/// How the progress of the run is streamed on stderr, apart from the results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EventFormat {
+    /// One JSON object per line for each test started and finished
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    Junit,
+    Html,
+    ScoreCsv,
+    ScoreJson,
+}
+
+/// A report file to write after the run, given as `kind=path`
+#[derive(Debug, Clone)]
+pub struct ReportTarget {
+    pub kind: ReportKind,
+    pub path: PathBuf,
+}
+
+impl FromStr for ReportTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected kind=path, found {s:?}"))?;
+        let kind = match kind {
+            "junit" => ReportKind::Junit,
+            "html" => ReportKind::Html,
+            "score-csv" => ReportKind::ScoreCsv,
+            "score-json" => ReportKind::ScoreJson,
+            _ => {
+                return Err(format!(
+                    "unknown report kind {kind:?}, expected junit, html, score-csv or score-json"
+                ))
+            }
+        };
+        Ok(Self {
+            kind,
+            path: path.into(),
+        })
+    }
+}
+
+impl ReportTarget {
+    /// `sources` are the YAML files each group was loaded from
+    pub fn write(
+        &self,
+        groups: &[GroupResult],
+        sources: &HashMap<String, PathBuf>,
+        score: &Score,
+    ) -> io::Result<()> {
+        match self.kind {
+            ReportKind::Junit => junit::write(&self.path, groups),
+            ReportKind::Html => html::write(&self.path, groups, sources),
+            ReportKind::ScoreCsv => score.write_csv(&mut File::create(&self.path)?),
+            ReportKind::ScoreJson => {
+                let mut file = File::create(&self.path)?;
+                serde_json::to_writer_pretty(&mut file, score)?;
+                writeln!(file)
+            }
+        }
+    }
+}
+
+pub struct Palette {
+    pub normal: ColorSpec,
+    pub ok: ColorSpec,
+    pub error: ColorSpec,
+    pub blue: ColorSpec,
+    pub bold: ColorSpec,
+    pub changed: ColorSpec,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        let mut ok = ColorSpec::new();
+        ok.set_fg(Some(Color::Green)).set_bold(true);
+        let mut error = ColorSpec::new();
+        error.set_fg(Some(Color::Red)).set_bold(true);
+        let mut blue = ColorSpec::new();
+        blue.set_fg(Some(Color::Blue)).set_bold(true);
+        let mut bold = ColorSpec::new();
+        bold.set_bold(true);
+        let mut changed = ColorSpec::new();
+        changed.set_fg(Some(Color::Yellow)).set_bold(true);
+        Self {
+            normal: ColorSpec::new(),
+            ok,
+            error,
+            blue,
+            bold,
+            changed,
+        }
+    }
+}
+
+/// Plain text version of a transcript, for reports that embed it
+pub fn transcript_text(transcript: &Transcript) -> String {
+    format!(
+        "--- emulator input ---\n{}--- emulator output ---\n{}",
+        transcript.stdin, transcript.stdout
+    )
+}
+
+/// Escapes text for XML and HTML, dropping the control characters they can't hold
+pub fn escape_markup(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    emulator::MemoryData,
+    locate::test_spans,
+    runner::{DataFailure, GroupResult, RunError, TestResult},
+};
+
+use super::{escape_markup, transcript_text};
+
+/// Bytes per row in memory diffs
+const ROW_BYTES: usize = 8;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h2 { margin-top: 2em; }
+table { border-collapse: collapse; margin: 0.5em 0; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+pre, code, .mono { font-family: monospace; }
+pre { background: #f6f6f6; padding: 0.5em; overflow-x: auto; }
+.passed { color: #1a7f37; font-weight: bold; }
+.failed, .error, .xpassed { color: #cf222e; font-weight: bold; }
+.skipped, .xfailed { color: #0550ae; font-weight: bold; }
+td.changed { background: #fff3bf; }
+td.bad, span.bad { background: #ffd7d5; }
+span.expected { color: #0550ae; }
+details { margin: 0.5em 0 1em 1em; }
+summary { cursor: pointer; }
+.yaml span { display: block; }
+.yaml span:target { background: #fff3bf; }
+.yaml span::before { content: attr(data-line); display: inline-block; width: 4em; color: #888; }
+";
+
+/// Writes a single HTML page with the results, the failure details and the
+/// YAML files the tests came from, so it can be shared as is
+pub fn write(
+    path: &Path,
+    groups: &[GroupResult],
+    sources: &HashMap<String, PathBuf>,
+) -> io::Result<()> {
+    let mut files: Vec<_> = groups
+        .iter()
+        .filter_map(|group| sources.get(&group.name))
+        .collect();
+    files.sort();
+    files.dedup();
+    let yamls: Vec<_> = files
+        .iter()
+        .map(|file| fs::read_to_string(file).unwrap_or_default())
+        .collect();
+    let spans: Vec<_> = yamls.iter().map(|yaml| test_spans(yaml)).collect();
+    let count = |status| {
+        groups
+            .iter()
+            .flat_map(|g| &g.tests)
+            .filter(|(_, r)| r.status() == status)
+            .count()
+    };
+
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>").unwrap();
+    writeln!(html, "<html><head><meta charset=\"utf-8\">").unwrap();
+    writeln!(html, "<title>Test results</title><style>{STYLE}</style>").unwrap();
+    writeln!(html, "</head><body>").unwrap();
+    writeln!(html, "<h1>Test results</h1>").unwrap();
+    writeln!(
+        html,
+        "<p><span class=\"passed\">{} passed</span>, <span class=\"failed\">{} failed</span>, <span class=\"error\">{} errors</span>, <span class=\"skipped\">{} skipped</span>, <span class=\"xfailed\">{} failed as expected</span>, <span class=\"xpassed\">{} passed unexpectedly</span></p>",
+        count("passed"),
+        count("failed"),
+        count("error"),
+        count("skipped"),
+        count("xfailed"),
+        count("xpassed")
+    )
+    .unwrap();
+
+    for group in groups {
+        writeln!(html, "<h2>{}</h2>", escape_markup(&group.name)).unwrap();
+        writeln!(
+            html,
+            "<table><tr><th>Test</th><th>Result</th><th>Reason</th><th>Time</th><th>Definition</th></tr>"
+        )
+        .unwrap();
+        for (name, result) in &group.tests {
+            let status = result.status();
+            let definition = sources
+                .get(&group.name)
+                .and_then(|source| files.iter().position(|file| *file == source))
+                .and_then(|index| {
+                    let span = spans[index].get(&(group.name.clone(), name.clone()))?;
+                    Some((index, span.start + 1))
+                })
+                .map_or_else(String::new, |(index, line)| {
+                    format!(
+                        "<a href=\"#yaml{index}-L{line}\">{}:{line}</a>",
+                        escape_markup(&files[index].display().to_string())
+                    )
+                });
+            writeln!(
+                html,
+                "<tr><td><a href=\"#{}\">{}</a></td><td class=\"{status}\">{status}</td><td>{}</td><td>{:.3} s</td><td>{definition}</td></tr>",
+                anchor(&group.name, name),
+                escape_markup(name),
+                escape_markup(result.reason().unwrap_or("")),
+                result.duration.as_secs_f64()
+            )
+            .unwrap();
+        }
+        writeln!(html, "</table>").unwrap();
+        for (name, result) in &group.tests {
+            if let Err(e) = &result.result {
+                write_failure(&mut html, &group.name, name, result, e);
+            }
+        }
+    }
+
+    writeln!(html, "<h2>Test definitions</h2>").unwrap();
+    for (index, (file, yaml)) in files.iter().zip(&yamls).enumerate() {
+        writeln!(
+            html,
+            "<p class=\"mono\">{}</p>",
+            escape_markup(&file.display().to_string())
+        )
+        .unwrap();
+        write!(html, "<pre class=\"yaml\">").unwrap();
+        for (i, line) in yaml.lines().enumerate() {
+            write!(
+                html,
+                "<span id=\"yaml{index}-L{n}\" data-line=\"{n}\">{}</span>",
+                escape_markup(line),
+                n = i + 1
+            )
+            .unwrap();
+        }
+        writeln!(html, "</pre>").unwrap();
+    }
+    writeln!(html, "</body></html>").unwrap();
+    fs::write(path, html)
+}
+
+fn anchor(group: &str, name: &str) -> String {
+    format!("test-{group}-{name}")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn write_failure(html: &mut String, group: &str, name: &str, result: &TestResult, e: &RunError) {
+    writeln!(
+        html,
+        "<details id=\"{}\"><summary><span class=\"{}\">{}</span>: {}</summary>",
+        anchor(group, name),
+        result.status(),
+        escape_markup(name),
+        escape_markup(&e.to_string())
+    )
+    .unwrap();
+    match e {
+        RunError::RegistersFailed(failures, _) => {
+            writeln!(html, "<ul>").unwrap();
+            for failure in failures {
+                writeln!(html, "<li>{}</li>", escape_markup(&failure.to_string())).unwrap();
+            }
+            writeln!(html, "</ul>").unwrap();
+            for failure in failures {
+                if let DataFailure::Memory(addr, expected, found) = failure {
+                    write_memory_diff(html, *addr, expected, found);
+                }
+            }
+            if let Some(run) = &result.run {
+                write_registers(html, run.registers(), &result.setup_registers, failures);
+            }
+        }
+        _ => writeln!(html, "<pre>{}</pre>", escape_markup(&e.details())).unwrap(),
+    }
+    if let Some(transcript) = &result.transcript {
+        writeln!(
+            html,
+            "<details><summary>Emulator transcript</summary><pre>{}</pre></details>",
+            escape_markup(&transcript_text(transcript))
+        )
+        .unwrap();
+    }
+    writeln!(html, "</details>").unwrap();
+}
+
+/// All the registers as an 8x4 table, with the ones that changed during the
+/// execution highlighted and the ones that failed a check in red
+fn write_registers(
+    html: &mut String,
+    registers: &[u32; 32],
+    setup: &[u32; 32],
+    failures: &[DataFailure],
+) {
+    writeln!(html, "<table class=\"mono\">").unwrap();
+    for (i, row) in registers.chunks(4).enumerate() {
+        write!(html, "<tr>").unwrap();
+        for (j, value) in row.iter().enumerate() {
+            let n = i * 4 + j;
+            let failed = failures
+                .iter()
+                .any(|f| matches!(f, DataFailure::Register(r, _, _) if r.number() as usize == n));
+            let class = if failed {
+                " class=\"bad\""
+            } else if *value != setup[n] {
+                " class=\"changed\""
+            } else {
+                ""
+            };
+            write!(html, "<td{class}>r{n:02} = {value:08X}</td>").unwrap();
+        }
+        writeln!(html, "</tr>").unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+}
+
+/// A side-by-side hexdump of the expected and found memory, or the two strings
+/// for text
+fn write_memory_diff(html: &mut String, addr: u32, expected: &MemoryData, found: &MemoryData) {
+    let (expected_bytes, found_bytes) = (expected.to_bytes(), found.to_bytes());
+    if let MemoryData::Text(text) = expected {
+        let end = found_bytes
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(found_bytes.len());
+        let found_text: String = found_bytes[..end].iter().map(|b| *b as char).collect();
+        writeln!(
+            html,
+            "<table class=\"mono\"><tr><th>expected</th><td><span class=\"expected\">{}</span></td></tr><tr><th>found</th><td><span class=\"bad\">{}</span></td></tr></table>",
+            escape_markup(&format!("{text:?}")),
+            escape_markup(&format!("{found_text:?}"))
+        )
+        .unwrap();
+        return;
+    }
+    writeln!(
+        html,
+        "<table class=\"mono\"><tr><th>address</th><th>expected</th><th>found</th></tr>"
+    )
+    .unwrap();
+    let rows = expected_bytes
+        .len()
+        .max(found_bytes.len())
+        .div_ceil(ROW_BYTES);
+    for row in 0..rows {
+        writeln!(
+            html,
+            "<tr><td>0x{:08X}</td><td>{}</td><td>{}</td></tr>",
+            addr as usize + row * ROW_BYTES,
+            bytes_row(&expected_bytes, &found_bytes, row * ROW_BYTES),
+            bytes_row(&found_bytes, &expected_bytes, row * ROW_BYTES)
+        )
+        .unwrap();
+    }
+    writeln!(html, "</table>").unwrap();
+}
+
+/// The bytes of one row, marking the ones that differ from `other`
+fn bytes_row(bytes: &[u8], other: &[u8], start: usize) -> String {
+    (start..start + ROW_BYTES)
+        .map(|i| match bytes.get(i) {
+            Some(b) if other.get(i) == Some(b) => format!("{b:02x}"),
+            Some(b) => format!("<span class=\"bad\">{b:02x}</span>"),
+            None => "&nbsp;&nbsp;".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,561 @@
+use std::io;
+
+use termcolor::{ColorSpec, WriteColor};
+
+use crate::{
+    emulator::{MemoryData, Transcript},
+    grading::Score,
+    runner::{DataFailure, GroupResult, RunError},
+};
+
+use super::Palette;
+
+/// Column where the first word of a memory dump line starts
+const DUMP_FIRST_WORD: usize = 17;
+/// Distance between two words of a memory dump line
+const DUMP_WORD_STRIDE: usize = 13;
+/// Bytes per row in memory diffs
+const ROW_BYTES: usize = 8;
+
+/// Writes the coloured, human oriented report of a run
+///
+/// The score is printed after the summary when there is one.
+pub fn write<W: WriteColor>(
+    out: &mut W,
+    groups: &[GroupResult],
+    verbose: bool,
+    score: Option<&Score>,
+) -> io::Result<()> {
+    let palette = Palette::new();
+    let mut failed_groups = Vec::with_capacity(groups.len());
+
+    let mut failed_tests = 0;
+    let mut ok_tests = 0;
+    let (mut skipped_tests, mut xfailed_tests, mut xpassed_tests) = (0, 0, 0);
+    for group in groups {
+        out.set_color(&palette.bold)?;
+        writeln!(out, "{}", group.name)?;
+        out.flush()?;
+        let mut failed_in_group = vec![];
+        for (test_name, result) in &group.tests {
+            out.set_color(&palette.normal)?;
+            write!(out, "{test_name:>30} ")?;
+            out.flush()?;
+            if let Some(reason) = &result.skipped {
+                skipped_tests += 1;
+                out.set_color(&palette.blue)?;
+                write!(out, "SKIPPED")?;
+                out.set_color(&palette.normal)?;
+                writeln!(out, " {reason}")?;
+                continue;
+            }
+            if let Some(reason) = &result.xfail {
+                if result.result.is_ok() {
+                    xpassed_tests += 1;
+                    failed_in_group.push((test_name, "passed, but was expected to fail"));
+                    out.set_color(&palette.error)?;
+                    write!(out, "XPASS")?;
+                } else {
+                    xfailed_tests += 1;
+                    out.set_color(&palette.blue)?;
+                    write!(out, "XFAIL")?;
+                }
+                out.set_color(&palette.normal)?;
+                writeln!(out, " {reason}")?;
+                continue;
+            }
+            match &result.result {
+                Ok(()) => {
+                    ok_tests += 1;
+                    out.set_color(&palette.ok)?;
+                    writeln!(out, "OK")?;
+                    out.flush()?;
+                }
+                Err(x) => {
+                    failed_in_group.push((test_name, "failed"));
+                    failed_tests += 1;
+                    out.set_color(&palette.error)?;
+                    writeln!(out, "ERROR")?;
+                    out.flush()?;
+                    out.set_color(&palette.normal)?;
+                    if let Some(generated) = &result.generated {
+                        writeln!(
+                            out,
+                            " =+= Failed on {generated}, run again with --seed {} =+=",
+                            generated.seed
+                        )?;
+                        for input in &generated.inputs {
+                            writeln!(out, "{:>8}{input}", "")?;
+                        }
+                    }
+                    match x {
+                        RunError::Files(e) => writeln!(out, "{:>20} writing its files: {e}", "")?,
+                        RunError::CompileExec(e) => writeln!(out, "{:>20} compiling: {e}", "")?,
+                        RunError::RunExec(e) => writeln!(out, "{:>20} running: {e}", "")?,
+                        // RunError::StopFailed(out) => {
+                        //     writeln!(out, "{:>20} didn't reach stop: {e}", "")?
+                        // }
+                        RunError::Compile(output) => {
+                            writeln!(out, "{:>20} compiling (OUTPUT):", "")?;
+                            writeln!(out, "STDOUT:")?;
+                            out.write_all(&output.stdout)?;
+                            writeln!(out, "STDERR:")?;
+                            out.write_all(&output.stderr)?;
+                            writeln!(out)?;
+                        }
+                        RunError::Run(output) => {
+                            writeln!(out, "{:>20} running (OUTPUT):", "")?;
+                            writeln!(out, "STDOUT:")?;
+                            out.write_all(&output.stdout)?;
+                            writeln!(out, "STDERR:")?;
+                            out.write_all(&output.stderr)?;
+                            writeln!(out)?;
+                        }
+                        RunError::Reference(e) => {
+                            writeln!(out, "{:>20} running the reference program:", "")?;
+                            writeln!(out, "{}", e.details())?;
+                        }
+                        RunError::Parse(e) => {
+                            writeln!(out, "{:>20} reading the emulator output: {e}", "")?;
+                            if let (true, Some(transcript)) = (verbose, &result.transcript) {
+                                write_transcript(out, &palette, transcript, &[], true)?;
+                            }
+                        }
+                        RunError::RegistersFailed(failures, stop_code) => {
+                            if let Some(code) = stop_code {
+                                out.set_color(&palette.normal)?;
+                                write!(out, " =+= Unexpected stop condition: ")?;
+                                out.set_color(&palette.error)?;
+                                write!(out, "{code}")?;
+                                out.set_color(&palette.normal)?;
+                                writeln!(out, " =+=")?;
+                                out.flush()?;
+                            }
+                            for failure in failures {
+                                if let DataFailure::Memory(
+                                    addr,
+                                    expected @ (MemoryData::Bytes(_) | MemoryData::Text(_)),
+                                    found,
+                                ) = failure
+                                {
+                                    write!(out, " =+= ")?;
+                                    out.set_color(&palette.blue)?;
+                                    write!(out, "m[0x{addr:X}]")?;
+                                    out.set_color(&palette.normal)?;
+                                    writeln!(out, " doesn't hold the expected value =+=")?;
+                                    write_memory_diff(out, &palette, *addr, expected, found)?;
+                                    continue;
+                                }
+                                let (name, expected, found) = match failure {
+                                    DataFailure::Register(a, b, c) => {
+                                        (format!("{a}"), format!("{b}"), format!("{c}"))
+                                    }
+                                    DataFailure::Memory(a, b, c) => {
+                                        (format!("m[0x{a:X}]"), format!("{b:?}"), format!("{c:?}"))
+                                    }
+                                };
+                                out.set_color(&palette.normal)?;
+                                write!(out, " =+= ")?;
+                                out.set_color(&palette.blue)?;
+                                write!(out, "{name}")?;
+                                out.set_color(&palette.normal)?;
+                                write!(out, " was ")?;
+                                out.set_color(&palette.error)?;
+                                write!(out, "{found}")?;
+                                out.set_color(&palette.normal)?;
+                                write!(out, ", but ")?;
+                                out.set_color(&palette.blue)?;
+                                write!(out, "{expected}")?;
+                                out.set_color(&palette.normal)?;
+                                writeln!(out, " was expected =+=")?;
+                                out.flush()?;
+                            }
+                            if let Some(run) = &result.run {
+                                write_registers(
+                                    out,
+                                    &palette,
+                                    run.registers(),
+                                    &result.setup_registers,
+                                    failures,
+                                )?;
+                            }
+                            if let (true, Some(transcript)) = (verbose, &result.transcript) {
+                                write_transcript(out, &palette, transcript, failures, false)?;
+                            }
+                            writeln!(out)?;
+                            out.flush()?;
+                        }
+                    }
+                    out.flush()?;
+                }
+            }
+        }
+        failed_groups.push((&group.name, group.tests.len(), failed_in_group));
+    }
+
+    out.set_color(&palette.normal)?;
+    for (group, total, failed) in failed_groups.iter().filter(|(_, _, f)| !f.is_empty()) {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "     {group} has failed tests: {}/{total}",
+            failed.len()
+        )?;
+        for (test, what) in failed {
+            writeln!(out, "       {test} {what}")?;
+        }
+    }
+    out.flush()?;
+    out.set_color(&palette.bold)?;
+    writeln!(out)?;
+    writeln!(out, "{failed_tests:>6} tests failed")?;
+    writeln!(out, "{ok_tests:>6} tests passed")?;
+    if skipped_tests != 0 {
+        writeln!(out, "{skipped_tests:>6} tests skipped")?;
+    }
+    if xfailed_tests != 0 {
+        writeln!(out, "{xfailed_tests:>6} tests failed as expected")?;
+    }
+    if xpassed_tests != 0 {
+        out.set_color(&palette.error)?;
+        writeln!(out, "{xpassed_tests:>6} tests passed unexpectedly")?;
+        out.set_color(&palette.bold)?;
+    }
+    if let Some(score) = score {
+        writeln!(out)?;
+        for group in &score.groups {
+            writeln!(out, "     {}: {}/{}", group.name, group.score, group.max)?;
+        }
+        writeln!(out, "{:>6}/{} points", score.score, score.max)?;
+    }
+    out.set_color(&palette.normal)?;
+    out.flush()
+}
+
+/// Writes a line for each test that failed, and the counts of each result
+pub fn write_summary<W: WriteColor>(out: &mut W, groups: &[GroupResult]) -> io::Result<()> {
+    let palette = Palette::new();
+    let tests = groups
+        .iter()
+        .flat_map(|group| group.tests.iter().map(move |test| (&group.name, test)));
+    let mut counts = [0; 6];
+    const STATUSES: [&str; 6] = ["passed", "failed", "error", "skipped", "xfailed", "xpassed"];
+    for (group, (name, result)) in tests {
+        let status = result.status();
+        if let Some(i) = STATUSES.iter().position(|s| *s == status) {
+            counts[i] += 1;
+        }
+        if result.is_failure() {
+            out.set_color(&palette.error)?;
+            write!(out, "{:>8} ", status.to_uppercase())?;
+            out.set_color(&palette.normal)?;
+            match &result.result {
+                Err(e) => writeln!(out, "{group}/{name}: {e}")?,
+                Ok(()) => writeln!(out, "{group}/{name}: passed, but was expected to fail")?,
+            }
+        }
+    }
+    writeln!(out)?;
+    for (i, (status, count)) in STATUSES.iter().zip(counts).enumerate() {
+        if count == 0 && i != 0 {
+            continue;
+        }
+        if i != 0 {
+            write!(out, ", ")?;
+        }
+        out.set_color(match *status {
+            "passed" => &palette.ok,
+            "failed" | "error" | "xpassed" => &palette.error,
+            _ => &palette.blue,
+        })?;
+        write!(out, "{count} {status}")?;
+        out.set_color(&palette.normal)?;
+    }
+    writeln!(out)?;
+    out.flush()
+}
+
+/// Writes all the registers as an 8x4 table, with the ones that changed during
+/// the execution highlighted and the ones that failed a check in red
+pub fn write_registers<W: WriteColor>(
+    out: &mut W,
+    palette: &Palette,
+    registers: &[u32; 32],
+    setup: &[u32; 32],
+    failures: &[DataFailure],
+) -> io::Result<()> {
+    for (i, row) in registers.chunks(4).enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            let n = i * 4 + j;
+            write!(out, "{}", if j == 0 { "    " } else { "   " })?;
+            let failed = failures
+                .iter()
+                .any(|f| matches!(f, DataFailure::Register(r, _, _) if r.number() as usize == n));
+            if failed {
+                out.set_color(&palette.error)?;
+            } else if *value != setup[n] {
+                out.set_color(&palette.changed)?;
+            } else {
+                out.set_color(&palette.normal)?;
+            }
+            write!(out, "r{n:02} = {value:08X}")?;
+            out.set_color(&palette.normal)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes the commands sent to the emulator and what it printed back.
+///
+/// Unless `full_output` is set only the dumps after the execution are shown, and
+/// the expected values of the failed checks are written under the dump lines
+/// they appear in, aligned with the values found.
+pub fn write_transcript<W: WriteColor>(
+    out: &mut W,
+    palette: &Palette,
+    transcript: &Transcript,
+    failures: &[DataFailure],
+    full_output: bool,
+) -> io::Result<()> {
+    out.set_color(&palette.bold)?;
+    writeln!(out, " --- emulator input ---")?;
+    out.set_color(&palette.normal)?;
+    for line in transcript.stdin.lines() {
+        writeln!(out, "   {line}")?;
+    }
+    out.set_color(&palette.bold)?;
+    writeln!(out, " --- emulator output ---")?;
+    out.set_color(&palette.normal)?;
+    if full_output {
+        for line in transcript.stdout.lines() {
+            writeln!(out, "   {line}")?;
+        }
+    } else {
+        for line in transcript.dump() {
+            writeln!(out, "   {line}")?;
+            if let Some(expected) = expected_line(line, failures) {
+                out.set_color(&palette.blue)?;
+                write!(out, "   {expected}")?;
+                out.set_color(&palette.normal)?;
+                writeln!(out, " (expected)")?;
+            }
+        }
+    }
+    out.set_color(&palette.bold)?;
+    writeln!(out, " ---")?;
+    out.set_color(&palette.normal)?;
+    Ok(())
+}
+
+/// Builds a line with the expected values placed at the same columns as the
+/// values found in a register or memory dump line
+fn expected_line(line: &str, failures: &[DataFailure]) -> Option<String> {
+    if !line.is_ascii() {
+        return None;
+    }
+    let mut expected = vec![b' '; line.len()];
+    let mut place = |col: usize, text: &str| {
+        if expected.len() < col + text.len() {
+            expected.resize(col + text.len(), b' ');
+        }
+        expected[col..col + text.len()].copy_from_slice(text.as_bytes());
+    };
+    let mut found = false;
+    let lower = line.to_ascii_lowercase();
+    let memory_base = line
+        .split_whitespace()
+        .next()
+        .filter(|_| line.len() > DUMP_FIRST_WORD)
+        .and_then(|a| u32::from_str_radix(a, 16).ok());
+    for failure in failures {
+        match failure {
+            DataFailure::Register(reg, value, _) => {
+                if let Some(col) = lower.find(&format!("{reg}")) {
+                    place(col, &format!("{} = {value:08X}h", &line[col..col + 3]));
+                    found = true;
+                }
+            }
+            DataFailure::Memory(addr, value, _) => {
+                let Some(base) = memory_base else { continue };
+                for (i, byte) in value.to_bytes().into_iter().enumerate() {
+                    let Some(offset) = (*addr + i as u32).checked_sub(base) else {
+                        continue;
+                    };
+                    if offset < 16 {
+                        let offset = offset as usize;
+                        let col =
+                            DUMP_FIRST_WORD + (offset / 4) * DUMP_WORD_STRIDE + (offset % 4) * 2;
+                        place(col, &format!("{byte:02X}"));
+                        found = true;
+                    }
+                }
+            }
+        }
+    }
+    found.then(|| String::from_utf8_lossy(&expected).trim_end().to_string())
+}
+
+/// Writes how the memory found differs from the expected one: a string diff for
+/// text and a side-by-side hexdump for anything else
+pub fn write_memory_diff<W: WriteColor>(
+    out: &mut W,
+    palette: &Palette,
+    addr: u32,
+    expected: &MemoryData,
+    found: &MemoryData,
+) -> io::Result<()> {
+    match expected {
+        MemoryData::Text(text) => write_text_diff(out, palette, text, &found.to_bytes()),
+        _ => write_bytes_diff(out, palette, addr, &expected.to_bytes(), &found.to_bytes()),
+    }
+}
+
+fn write_text_diff<W: WriteColor>(
+    out: &mut W,
+    palette: &Palette,
+    expected: &str,
+    found: &[u8],
+) -> io::Result<()> {
+    let end = found.iter().position(|b| *b == 0);
+    let found_text: String = found[..end.unwrap_or(found.len())]
+        .iter()
+        .map(|b| *b as char)
+        .collect();
+    let expected_escaped = format!("{expected:?}");
+    let found_escaped = format!("{found_text:?}");
+
+    write!(out, "    expected ")?;
+    out.set_color(&palette.blue)?;
+    writeln!(out, "{expected_escaped}")?;
+    out.set_color(&palette.normal)?;
+    write!(out, "    found    ")?;
+    out.set_color(&palette.error)?;
+    write!(out, "{found_escaped}")?;
+    out.set_color(&palette.normal)?;
+    if end.is_none() {
+        write!(out, " (no null terminator)")?;
+    }
+    writeln!(out)?;
+
+    let expected_bytes = expected.as_bytes();
+    let mismatch = expected_bytes
+        .iter()
+        .zip(found)
+        .position(|(e, f)| e != f)
+        .or_else(|| (found.len() < expected_bytes.len()).then_some(found.len()));
+    if let Some(index) = mismatch {
+        // The caret only lines up when nothing before it had to be escaped
+        if expected_bytes[..index]
+            .iter()
+            .all(|b| (b.is_ascii_graphic() || *b == b' ') && !matches!(b, b'"' | b'\\'))
+        {
+            writeln!(out, "    {:>width$}^", "", width = 10 + index)?;
+        }
+        match found.get(index) {
+            Some(0) => writeln!(
+                out,
+                "    The string ends at index {index}, but {} characters were expected",
+                expected_bytes.len()
+            )?,
+            Some(b) => writeln!(
+                out,
+                "    First difference at index {index}: expected {:?} (0x{:02x}), found {:?} (0x{b:02x})",
+                expected_bytes[index] as char, expected_bytes[index], *b as char
+            )?,
+            None => writeln!(out, "    The string ends at index {index}")?,
+        }
+    } else {
+        match found.get(expected_bytes.len()) {
+            Some(0) | None => {}
+            Some(b) => writeln!(
+                out,
+                "    Missing null terminator at index {}, extra characters follow: {:?} (0x{b:02x})...",
+                expected_bytes.len(),
+                *b as char
+            )?,
+        }
+    }
+    Ok(())
+}
+
+fn write_bytes_diff<W: WriteColor>(
+    out: &mut W,
+    palette: &Palette,
+    addr: u32,
+    expected: &[u8],
+    found: &[u8],
+) -> io::Result<()> {
+    const ROW_WIDTH: usize = ROW_BYTES * 3 - 1;
+
+    let rows = expected.len().max(found.len()).div_ceil(ROW_BYTES);
+    let row_differs = |row: usize| {
+        (row * ROW_BYTES..(row + 1) * ROW_BYTES).any(|i| expected.get(i) != found.get(i))
+    };
+    writeln!(
+        out,
+        "    {:<10}   {:<ROW_WIDTH$}   found",
+        "address", "expected"
+    )?;
+    let mut skipping = false;
+    for row in 0..rows {
+        // Only rows with differences are shown, with one row of context around them
+        let near_difference = row_differs(row)
+            || (row > 0 && row_differs(row - 1))
+            || (row + 1 < rows && row_differs(row + 1));
+        if !near_difference {
+            if !skipping {
+                writeln!(out, "    ...")?;
+                skipping = true;
+            }
+            continue;
+        }
+        skipping = false;
+        write!(out, "    0x{:08X}   ", addr as usize + row * ROW_BYTES)?;
+        write_bytes_row(
+            out,
+            palette,
+            &palette.blue,
+            expected,
+            found,
+            row * ROW_BYTES,
+        )?;
+        write!(out, "   ")?;
+        write_bytes_row(
+            out,
+            palette,
+            &palette.error,
+            found,
+            expected,
+            row * ROW_BYTES,
+        )?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Writes a row of a hexdump, highlighting the bytes that differ from `other`
+fn write_bytes_row<W: WriteColor>(
+    out: &mut W,
+    palette: &Palette,
+    highlight: &ColorSpec,
+    bytes: &[u8],
+    other: &[u8],
+    start: usize,
+) -> io::Result<()> {
+    for i in start..start + ROW_BYTES {
+        if i != start {
+            write!(out, " ")?;
+        }
+        match bytes.get(i) {
+            Some(b) if other.get(i) != Some(b) => {
+                out.set_color(highlight)?;
+                write!(out, "{b:02x}")?;
+                out.set_color(&palette.normal)?;
+            }
+            Some(b) => write!(out, "{b:02x}")?,
+            None => write!(out, "  ")?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::{io, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    emulator::MemoryData,
+    generate::Generated,
+    grading::Score,
+    runner::{DataFailure, GroupResult, RunError, TestResult},
+    threadpool::Event,
+};
+
+#[derive(Serialize)]
+struct Document<'a> {
+    passed: usize,
+    failed: usize,
+    errors: usize,
+    skipped: usize,
+    xfailed: usize,
+    xpassed: usize,
+    duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<&'a Score>,
+    groups: Vec<Group<'a>>,
+}
+
+#[derive(Serialize)]
+struct Group<'a> {
+    name: &'a str,
+    tests: Vec<Test<'a>>,
+}
+
+#[derive(Serialize)]
+struct Test<'a> {
+    name: &'a str,
+    status: &'static str,
+    /// Why the test was skipped or expected to fail
+    reason: Option<&'a str>,
+    duration_ms: u128,
+    stop_code: Option<&'a str>,
+    error: Option<Error>,
+    failures: Vec<Failure<'a>>,
+    /// The case a test with generated inputs failed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generated: Option<&'a Generated>,
+}
+
+#[derive(Serialize)]
+struct Error {
+    kind: &'static str,
+    message: String,
+    details: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Failure<'a> {
+    Register {
+        register: String,
+        expected: u32,
+        found: u32,
+    },
+    Memory {
+        address: u32,
+        expected: &'a MemoryData,
+        found: &'a MemoryData,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum EventLine<'a> {
+    Started {
+        id: usize,
+        name: &'a str,
+    },
+    Finished {
+        id: usize,
+        name: &'a str,
+        status: &'a str,
+    },
+}
+
+impl<'a> Test<'a> {
+    fn new(name: &'a str, result: &'a TestResult) -> Self {
+        let failures = match &result.result {
+            Err(RunError::RegistersFailed(failures, _)) => failures
+                .iter()
+                .map(|failure| match failure {
+                    DataFailure::Register(register, expected, found) => Failure::Register {
+                        register: register.to_string(),
+                        expected: *expected,
+                        found: *found,
+                    },
+                    DataFailure::Memory(address, expected, found) => Failure::Memory {
+                        address: *address,
+                        expected,
+                        found,
+                    },
+                })
+                .collect(),
+            _ => vec![],
+        };
+        Self {
+            name,
+            status: result.status(),
+            reason: result.reason(),
+            duration_ms: result.duration.as_millis(),
+            stop_code: result.run.as_ref().and_then(|run| run.get_stop_code()),
+            error: result.result.as_ref().err().map(|e| Error {
+                kind: e.kind(),
+                message: e.to_string(),
+                details: e.details(),
+            }),
+            failures,
+            generated: result.generated.as_ref(),
+        }
+    }
+}
+
+/// Writes the results of the run as a single JSON document
+pub fn write<W: io::Write>(
+    out: &mut W,
+    groups: &[GroupResult],
+    duration: Duration,
+    score: Option<&Score>,
+) -> io::Result<()> {
+    let count = |status| {
+        groups
+            .iter()
+            .flat_map(|g| &g.tests)
+            .filter(|(_, r)| r.status() == status)
+            .count()
+    };
+    let document = Document {
+        passed: count("passed"),
+        failed: count("failed"),
+        errors: count("error"),
+        skipped: count("skipped"),
+        xfailed: count("xfailed"),
+        xpassed: count("xpassed"),
+        duration_ms: duration.as_millis(),
+        score,
+        groups: groups
+            .iter()
+            .map(|group| Group {
+                name: &group.name,
+                tests: group
+                    .tests
+                    .iter()
+                    .map(|(name, result)| Test::new(name, result))
+                    .collect(),
+            })
+            .collect(),
+    };
+    serde_json::to_writer(&mut *out, &document)?;
+    writeln!(out)
+}
+
+/// Writes a thread pool event as a line of NDJSON
+pub fn write_event<W: io::Write>(out: &mut W, event: &Event) -> io::Result<()> {
+    let line = match event {
+        Event::Started { id, name } => EventLine::Started { id: *id, name },
+        Event::Finished { id, name, outcome } => EventLine::Finished {
+            id: *id,
+            name,
+            status: outcome,
+        },
+    };
+    serde_json::to_writer(&mut *out, &line)?;
+    writeln!(out)?;
+    out.flush()
+}
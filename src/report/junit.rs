@@ -0,0 +1,110 @@
+use std::{fmt::Write as _, fs, io, path::Path};
+
+use crate::runner::GroupResult;
+
+use super::{escape_markup, transcript_text};
+
+/// Writes the results as JUnit XML, one `<testsuite>` per group
+pub fn write(path: &Path, groups: &[GroupResult]) -> io::Result<()> {
+    // Tests expected to fail are reported as skipped, unless they passed
+    let count = |group: &GroupResult, statuses: &[&str]| {
+        group
+            .tests
+            .iter()
+            .filter(|(_, r)| statuses.contains(&r.status()))
+            .count()
+    };
+    let (failure, error, skipped) = (
+        &["failed", "xpassed"][..],
+        &["error"][..],
+        &["skipped", "xfailed"][..],
+    );
+    let total: usize = groups.iter().map(|g| g.tests.len()).sum();
+    let failures: usize = groups.iter().map(|g| count(g, failure)).sum();
+    let errors: usize = groups.iter().map(|g| count(g, error)).sum();
+    let skips: usize = groups.iter().map(|g| count(g, skipped)).sum();
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(
+        xml,
+        r#"<testsuites name="m88k" tests="{total}" failures="{failures}" errors="{errors}" skipped="{skips}">"#
+    )
+    .unwrap();
+    for group in groups {
+        writeln!(
+            xml,
+            r#"  <testsuite id="{}" name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+            group.id,
+            escape_markup(&group.name),
+            group.tests.len(),
+            count(group, failure),
+            count(group, error),
+            count(group, skipped),
+            group
+                .tests
+                .iter()
+                .map(|(_, r)| r.duration.as_secs_f64())
+                .sum::<f64>(),
+        )
+        .unwrap();
+        for (name, result) in &group.tests {
+            writeln!(
+                xml,
+                r#"    <testcase name="{}" classname="{}" time="{:.3}">"#,
+                escape_markup(name),
+                escape_markup(&group.name),
+                result.duration.as_secs_f64()
+            )
+            .unwrap();
+            if let Some(reason) = &result.skipped {
+                writeln!(
+                    xml,
+                    r#"      <skipped message="{}"/>"#,
+                    escape_markup(reason)
+                )
+                .unwrap();
+            } else if let (Some(reason), Err(_)) = (&result.xfail, &result.result) {
+                writeln!(
+                    xml,
+                    r#"      <skipped message="expected to fail: {}"/>"#,
+                    escape_markup(reason)
+                )
+                .unwrap();
+            } else if let (Some(reason), Ok(())) = (&result.xfail, &result.result) {
+                writeln!(
+                    xml,
+                    r#"      <failure message="passed, but was expected to fail: {}" type="xpass"/>"#,
+                    escape_markup(reason)
+                )
+                .unwrap();
+            } else if let Err(e) = &result.result {
+                let tag = if e.is_check_failure() {
+                    "failure"
+                } else {
+                    "error"
+                };
+                writeln!(
+                    xml,
+                    r#"      <{tag} message="{}" type="{}">{}</{tag}>"#,
+                    escape_markup(&e.to_string()),
+                    e.kind(),
+                    escape_markup(&e.details())
+                )
+                .unwrap();
+            }
+            if let Some(transcript) = &result.transcript {
+                writeln!(
+                    xml,
+                    "      <system-out>{}</system-out>",
+                    escape_markup(&transcript_text(transcript))
+                )
+                .unwrap();
+            }
+            writeln!(xml, "    </testcase>").unwrap();
+        }
+        writeln!(xml, "  </testsuite>").unwrap();
+    }
+    writeln!(xml, "</testsuites>").unwrap();
+    fs::write(path, xml)
+}
@@ -0,0 +1,116 @@
+use std::io;
+
+use serde::Serialize;
+
+use crate::{
+    grading::Score,
+    runner::{DataFailure, GroupResult, RunError},
+};
+
+/// YAML diagnostics block of a `not ok` line
+#[derive(Serialize)]
+struct Diagnostics {
+    message: String,
+    severity: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_code: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failures: Vec<Failure>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Failure {
+    Register {
+        register: String,
+        expected: u32,
+        found: u32,
+    },
+    Memory {
+        address: String,
+        expected: String,
+        found: String,
+    },
+}
+
+impl Diagnostics {
+    fn new(error: &RunError) -> Self {
+        let (failures, stop_code) = match error {
+            RunError::RegistersFailed(failures, stop_code) => (
+                failures
+                    .iter()
+                    .map(|failure| match failure {
+                        DataFailure::Register(register, expected, found) => Failure::Register {
+                            register: register.to_string(),
+                            expected: *expected,
+                            found: *found,
+                        },
+                        DataFailure::Memory(address, expected, found) => Failure::Memory {
+                            address: format!("0x{address:X}"),
+                            expected: expected.to_string(),
+                            found: found.to_string(),
+                        },
+                    })
+                    .collect(),
+                stop_code.clone(),
+            ),
+            _ => (vec![], None),
+        };
+        Self {
+            message: error.to_string(),
+            severity: if error.is_check_failure() {
+                "fail"
+            } else {
+                "error"
+            },
+            stop_code,
+            failures,
+            details: (!error.is_check_failure()).then(|| error.details()),
+        }
+    }
+}
+
+/// Writes the results as TAP version 13, one test point per test, and the
+/// score as a comment at the end
+pub fn write<W: io::Write>(
+    out: &mut W,
+    groups: &[GroupResult],
+    score: Option<&Score>,
+) -> io::Result<()> {
+    writeln!(out, "TAP version 13")?;
+    writeln!(
+        out,
+        "1..{}",
+        groups.iter().map(|g| g.tests.len()).sum::<usize>()
+    )?;
+    let tests = groups
+        .iter()
+        .flat_map(|group| group.tests.iter().map(move |test| (&group.name, test)));
+    for (i, (group, (name, result))) in tests.enumerate() {
+        let description = format!("{group}/{name}").replace('#', "\\#");
+        // Tests expected to fail are TODO tests: the harness reports them when they pass
+        let directive = match (&result.skipped, &result.xfail) {
+            (Some(reason), _) => format!(" # SKIP {reason}"),
+            (None, Some(reason)) => format!(" # TODO {reason}"),
+            (None, None) => String::new(),
+        };
+        match &result.result {
+            Ok(()) => writeln!(out, "ok {} - {description}{directive}", i + 1)?,
+            Err(e) => {
+                writeln!(out, "not ok {} - {description}{directive}", i + 1)?;
+                let yaml = serde_yaml::to_string(&Diagnostics::new(e)).map_err(io::Error::other)?;
+                writeln!(out, "  ---")?;
+                for line in yaml.lines() {
+                    writeln!(out, "  {line}")?;
+                }
+                writeln!(out, "  ...")?;
+            }
+        }
+    }
+    if let Some(score) = score {
+        writeln!(out, "# score {}/{}", score.score, score.max)?;
+    }
+    out.flush()
+}
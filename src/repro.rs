@@ -0,0 +1,70 @@
+use std::{env::current_dir, ffi::OsStr, fmt::Write as _, fs, io, path::Path, process::Command};
+
+use crate::emulator::Transcript;
+
+pub const SCRIPT_FILE: &str = "repro.sh";
+pub const COMMANDS_FILE: &str = "emulator.in";
+
+/// Writes a shell script (and the emulator command file) that repeats the
+/// assembler call and, if it got that far, the emulator session of a test.
+///
+/// Running the script with `-i` starts the emulator interactively instead,
+/// after printing the commands so they can be typed in by hand.
+pub fn write_repro(
+    dir: &Path,
+    title: &str,
+    assembler: &Command,
+    emulator: Option<(&Command, &Transcript)>,
+) -> io::Result<()> {
+    let mut script = String::new();
+    writeln!(script, "#!/bin/sh").unwrap();
+    writeln!(script, "# Reproduces {title}").unwrap();
+    writeln!(script, "# Run with -i to use the emulator interactively").unwrap();
+    writeln!(script, "set -e").unwrap();
+    writeln!(script, "DIR=\"$(cd \"$(dirname \"$0\")\" && pwd)\"").unwrap();
+    writeln!(script).unwrap();
+    writeln!(script, "# Assembler").unwrap();
+    writeln!(script, "({})", command_line(assembler)).unwrap();
+    if let Some((emulator, transcript)) = emulator {
+        fs::write(dir.join(COMMANDS_FILE), &transcript.stdin)?;
+        let emulator = command_line(emulator);
+        writeln!(script).unwrap();
+        writeln!(script, "# Emulator").unwrap();
+        writeln!(script, "if [ \"$1\" = \"-i\" ]; then").unwrap();
+        writeln!(script, "    cat \"$DIR/{COMMANDS_FILE}\"").unwrap();
+        writeln!(script, "    {emulator}").unwrap();
+        writeln!(script, "else").unwrap();
+        writeln!(script, "    {emulator} < \"$DIR/{COMMANDS_FILE}\"").unwrap();
+        writeln!(script, "fi").unwrap();
+    }
+    let script_path = dir.join(SCRIPT_FILE);
+    fs::write(&script_path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// `cd` into the command's directory, then run it with its environment
+fn command_line(command: &Command) -> String {
+    let cwd = command
+        .get_current_dir()
+        .map_or_else(|| current_dir().unwrap(), Path::to_path_buf);
+    let mut line = format!("cd {} &&", quote(cwd.as_os_str()));
+    for (key, value) in command.get_envs() {
+        if let Some(value) = value {
+            write!(line, " {}={}", key.to_string_lossy(), quote(value)).unwrap();
+        }
+    }
+    write!(line, " {}", quote(command.get_program())).unwrap();
+    for arg in command.get_args() {
+        write!(line, " {}", quote(arg)).unwrap();
+    }
+    line
+}
+
+fn quote(s: &OsStr) -> String {
+    format!("'{}'", s.to_string_lossy().replace('\'', "'\\''"))
+}
@@ -0,0 +1,368 @@
+use std::{
+    fmt::Display,
+    ops::Deref,
+    process::Output,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compiler::Compiler,
+    emulator::{self, Emulator, GPRegister, MemoryData, Operation, RunResult, Transcript},
+    generate::Generated,
+    tests::{self, TestData},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataFailure {
+    Register(GPRegister, u32, u32),
+    Memory(u32, MemoryData, MemoryData),
+    // StopFailed(String),
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    /// The files the test runs with couldn't be written
+    Files(std::io::Error),
+    CompileExec(std::io::Error),
+    Compile(Output),
+    RunExec(std::io::Error),
+    Run(Output),
+    /// The emulator output couldn't be understood
+    Parse(String),
+    RegistersFailed(Vec<DataFailure>, Option<String>),
+    /// The reference program couldn't be run to compare with it
+    Reference(Box<RunError>),
+}
+
+impl Display for DataFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Register(reg, expected, found) => {
+                write!(f, "{reg} was {found}, but {expected} was expected")
+            }
+            Self::Memory(addr, expected, found) => {
+                write!(f, "m[0x{addr:X}] was {found}, but {expected} was expected")
+            }
+        }
+    }
+}
+
+impl RunError {
+    /// Whether the test ran and the checks failed, rather than the test not being able to run
+    pub const fn is_check_failure(&self) -> bool {
+        matches!(self, Self::RegistersFailed(_, _))
+    }
+
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Files(_) => "files",
+            Self::CompileExec(_) => "compile_exec",
+            Self::Compile(_) => "compile",
+            Self::RunExec(_) => "run_exec",
+            Self::Run(_) => "run",
+            Self::Parse(_) => "parse",
+            Self::RegistersFailed(_, _) => "checks",
+            Self::Reference(_) => "reference",
+        }
+    }
+
+    /// Everything known about the error, one item per line
+    pub fn details(&self) -> String {
+        match self {
+            Self::Files(_) | Self::CompileExec(_) | Self::RunExec(_) | Self::Parse(_) => {
+                self.to_string()
+            }
+            Self::Compile(out) | Self::Run(out) => format!(
+                "{self}\nSTDOUT:\n{}\nSTDERR:\n{}",
+                String::from_utf8_lossy(&out.stdout),
+                String::from_utf8_lossy(&out.stderr)
+            ),
+            Self::RegistersFailed(failures, stop_code) => stop_code
+                .iter()
+                .map(|code| format!("Unexpected stop condition: {code}"))
+                .chain(failures.iter().map(ToString::to_string))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Self::Reference(e) => format!("The reference program failed\n{}", e.details()),
+        }
+    }
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Files(e) => write!(f, "couldn't write the files of the test: {e}"),
+            Self::CompileExec(e) => write!(f, "couldn't run the assembler: {e}"),
+            Self::Compile(out) => write!(f, "assembling failed ({})", out.status),
+            Self::RunExec(e) => write!(f, "couldn't run the emulator: {e}"),
+            Self::Run(out) => write!(f, "the emulator failed ({})", out.status),
+            Self::Parse(e) => write!(f, "couldn't read the emulator output: {e}"),
+            Self::RegistersFailed(failures, stop_code) => {
+                write!(f, "{} check(s) failed", failures.len())?;
+                if let Some(code) = stop_code {
+                    write!(f, ", unexpected stop condition: {code}")?;
+                }
+                Ok(())
+            }
+            Self::Reference(e) => write!(f, "the reference program failed: {e}"),
+        }
+    }
+}
+
+/// Outcome of a test, along with what was exchanged with the emulator
+#[derive(Debug)]
+pub struct TestResult {
+    pub result: Result<(), RunError>,
+    pub transcript: Option<Transcript>,
+    /// Register values given in the setup, the rest are assumed to start at 0
+    pub setup_registers: [u32; 32],
+    /// What the emulator reported, if it got to run
+    pub run: Option<RunResult>,
+    pub duration: Duration,
+    /// Why the test wasn't run, if it was skipped
+    pub skipped: Option<String>,
+    /// Why the test was expected to fail, if it was
+    pub xfail: Option<String>,
+    /// The case it failed with, for a test with generated inputs
+    pub generated: Option<Generated>,
+}
+
+impl TestResult {
+    /// Result of a test that wasn't run
+    pub const fn skipped(reason: String) -> Self {
+        Self {
+            result: Ok(()),
+            transcript: None,
+            setup_registers: [0; 32],
+            run: None,
+            duration: Duration::ZERO,
+            skipped: Some(reason),
+            xfail: None,
+            generated: None,
+        }
+    }
+
+    /// Result of a test that couldn't get to run
+    pub const fn errored(error: RunError) -> Self {
+        Self {
+            result: Err(error),
+            transcript: None,
+            setup_registers: [0; 32],
+            run: None,
+            duration: Duration::ZERO,
+            skipped: None,
+            xfail: None,
+            generated: None,
+        }
+    }
+
+    /// `passed`, `failed` if a check failed, `error` if the test couldn't run,
+    /// `skipped`, or `xfailed`/`xpassed` for tests expected to fail
+    pub const fn status(&self) -> &'static str {
+        match (&self.result, &self.skipped, &self.xfail) {
+            (_, Some(_), _) => "skipped",
+            (Ok(()), None, Some(_)) => "xpassed",
+            (Err(_), None, Some(_)) => "xfailed",
+            (Ok(()), None, None) => "passed",
+            (Err(e), None, None) if e.is_check_failure() => "failed",
+            (Err(_), None, None) => "error",
+        }
+    }
+
+    /// Whether the test makes the run fail: it failed, or passed when it was
+    /// expected to fail
+    pub const fn is_failure(&self) -> bool {
+        match (&self.result, &self.skipped, &self.xfail) {
+            (_, Some(_), _) => false,
+            (result, None, xfail) => result.is_ok() == xfail.is_some(),
+        }
+    }
+
+    /// Why the test was skipped or expected to fail
+    pub fn reason(&self) -> Option<&str> {
+        self.skipped.as_deref().or(self.xfail.as_deref())
+    }
+}
+
+/// Results of the tests of a group, in the order they were run
+#[derive(Debug)]
+pub struct GroupResult {
+    pub id: usize,
+    pub name: String,
+    pub tests: Vec<(String, TestResult)>,
+}
+
+/// Runs a test and checks its results, and compares them with the ones of the
+/// reference program if there is one
+pub fn run_test(
+    assembler: &Compiler,
+    emulator: &mut Emulator,
+    reference: Option<(&Compiler, &mut Emulator)>,
+    test_name: &str,
+    registers: &TestData,
+) -> TestResult {
+    let mut setup_registers = [0; 32];
+    if let TestData::WithSetup { setup, .. } = registers {
+        for check in setup.iter() {
+            if let tests::TestCheck::Register(reg, val) = check {
+                setup_registers[reg.number() as usize] = *val;
+            }
+        }
+    }
+    let mut run = None;
+    let start = Instant::now();
+    let result = check_test(
+        assembler, emulator, reference, test_name, registers, &mut run,
+    );
+    TestResult {
+        result,
+        transcript: emulator.take_transcript(),
+        setup_registers,
+        run,
+        duration: start.elapsed(),
+        skipped: None,
+        xfail: registers.get_xfail().map(ToString::to_string),
+        generated: None,
+    }
+}
+
+fn check_test(
+    assembler: &Compiler,
+    emulator: &mut Emulator,
+    reference: Option<(&Compiler, &mut Emulator)>,
+    test_name: &str,
+    test: &TestData,
+    run: &mut Option<RunResult>,
+) -> Result<(), RunError> {
+    let mut error_mem = Vec::new();
+    let (entrypoint, registers, operations) = match test {
+        TestData::NoSetup(checks) => (None, checks, vec![]),
+        TestData::WithSetup {
+            entrypoint,
+            setup,
+            checks,
+            ..
+        } => (
+            entrypoint.clone(),
+            checks,
+            setup
+                .iter()
+                .filter_map(|x| match x {
+                    tests::TestCheck::Register(reg, val) => Some(Operation::SetReg(*reg, *val)),
+                    tests::TestCheck::Memory(addr, data) => {
+                        if addr % 4 == 0 {
+                            let r = data.words().ok().map(|w| Operation::SetMem(*addr, w));
+                            if r.is_none() {
+                                error_mem.push(*addr);
+                            }
+                            r
+                        } else {
+                            error_mem.push(*addr);
+                            None
+                        }
+                    }
+                })
+                .collect(),
+        ),
+    };
+    if !error_mem.is_empty() {
+        eprintln!(
+            "Memory setup can only be done at word level, error for addresses: {error_mem:?}"
+        );
+    }
+    let entrypoint = entrypoint.as_deref().unwrap_or(test_name);
+    let mut memory_tests = registers
+        .iter()
+        .filter_map(|c| match c {
+            tests::TestCheck::Register(_, _) => None,
+            tests::TestCheck::Memory(addr, data) => Some((*addr, data.len_real())),
+        })
+        .collect::<Vec<_>>();
+    // The memory only compared with the reference, unless it is also checked
+    let compared = test
+        .get_compare()
+        .iter()
+        .filter(|(addr, _)| !memory_tests.iter().any(|(a, _)| a == addr))
+        .copied()
+        .collect::<Vec<_>>();
+    memory_tests.extend(&compared);
+
+    let run_res = run.insert(execute(
+        assembler,
+        emulator,
+        entrypoint,
+        &operations,
+        &memory_tests,
+    )?);
+    // println!("{r1:?}");
+    let mut res = vec![];
+    for check in registers.deref() {
+        match check {
+            tests::TestCheck::Register(register, val) => {
+                let val = *val;
+                let found = run_res.get_reg(register);
+                if val != found {
+                    res.push(DataFailure::Register(*register, val, found));
+                }
+            }
+            tests::TestCheck::Memory(addr, data) => {
+                let val = data.clone();
+                let found = run_res.get_mem(*addr).unwrap().clone();
+                if val != found {
+                    res.push(DataFailure::Memory(*addr, val, found));
+                }
+            }
+        }
+    }
+    if let Some((assembler, emulator)) = reference {
+        let expected = execute(assembler, emulator, entrypoint, &operations, &memory_tests)
+            .map_err(|e| RunError::Reference(Box::new(e)))?;
+        // Whatever the checks name was already compared with them
+        for register in (0..32).filter_map(GPRegister::new) {
+            let checked = registers
+                .iter()
+                .any(|c| matches!(c, tests::TestCheck::Register(r, _) if *r == register));
+            let (val, found) = (expected.get_reg(&register), run_res.get_reg(&register));
+            if !checked && val != found {
+                res.push(DataFailure::Register(register, val, found));
+            }
+        }
+        for (addr, _) in &compared {
+            let val = expected.get_mem(*addr).unwrap().clone();
+            let found = run_res.get_mem(*addr).unwrap().clone();
+            if val != found {
+                res.push(DataFailure::Memory(*addr, val, found));
+            }
+        }
+    }
+    if res.is_empty() {
+        Ok(())
+    } else {
+        Err(RunError::RegistersFailed(
+            res,
+            run_res.get_stop_code().map(ToString::to_string),
+        ))
+    }
+}
+
+/// Assembles the program at `entrypoint` and runs it with the setup, reading
+/// back the memory asked for
+fn execute(
+    assembler: &Compiler,
+    emulator: &mut Emulator,
+    entrypoint: &str,
+    operations: &[Operation],
+    memory_tests: &[(u32, u32)],
+) -> Result<RunResult, RunError> {
+    let assembled = assembler.run(entrypoint).map_err(RunError::CompileExec)?;
+    if !assembled.status.success() {
+        return Err(RunError::Compile(assembled));
+    }
+    // println!("\tCompile OK");
+    emulator.run(operations, memory_tests).map_err(|e| match e {
+        emulator::EmulatorError::Failure(e) => RunError::Run(e),
+        emulator::EmulatorError::IO(e) => RunError::RunExec(e),
+        emulator::EmulatorError::Parse(e) => RunError::Parse(e),
+        // emulator::EmulatorError::Unfinished(e) => RunError::StopFailed(e),
+    })
+}
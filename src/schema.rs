@@ -0,0 +1,341 @@
+use serde_json::{json, Value};
+
+const REGISTER: &str = "^[rR]([0-9]|[12][0-9]|3[01])$";
+const ADDRESS: &str = r"^[mM]\((0x[0-9a-fA-F]+|[0-9]+)\)$";
+
+/// JSON Schema of the test files, for editors to complete and check them as
+/// they are written, e.g. with `# yaml-language-server: $schema=schema.json`
+pub fn schema() -> Value {
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Test file",
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "config": { "$ref": "#/definitions/config" },
+            "include": {
+                "description": "Other test files, or glob patterns of them, relative to this one",
+                "oneOf": [
+                    { "type": "string" },
+                    { "type": "array", "items": { "type": "string" } }
+                ]
+            },
+            "fixtures": {
+                "description": "Pieces of tests, by name, that tests can `use:`",
+                "type": "object",
+                "additionalProperties": { "$ref": "#/definitions/test" }
+            },
+            "tests": {
+                "description": "Groups of tests, by name",
+                "type": "object",
+                "additionalProperties": { "$ref": "#/definitions/group" }
+            }
+        },
+        "definitions": {
+            "config": config(),
+            "group": group(),
+            "test": test(),
+            "settings": settings(),
+            "checks": checks(),
+            "register": {
+                "description": "A register value, from 0 to 0xFFFFFFFF",
+                "type": "integer",
+                "minimum": 0,
+                "maximum": u32::MAX
+            },
+            "memory": memory(),
+            "generate": generate(),
+            "domain": domain(),
+            "length": {
+                "description": "A length, or [min, max] to pick one between both",
+                "oneOf": [
+                    { "type": "integer", "minimum": 0 },
+                    { "$ref": "#/definitions/range" }
+                ]
+            },
+            "range": {
+                "type": "array",
+                "items": { "type": "integer" },
+                "minItems": 2,
+                "maxItems": 2
+            },
+            "names": {
+                "oneOf": [
+                    { "type": "string" },
+                    { "type": "array", "items": { "type": "string" } }
+                ]
+            }
+        }
+    })
+}
+
+fn config() -> Value {
+    let path = |description: &str| json!({ "description": description, "type": "string" });
+    let command = |description: &str| json!({ "description": description, "type": "array", "items": { "type": "string" } });
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "assembler": path("The assembler, found in the PATH when given by name only"),
+            "emulator": path("The emulator, found in the PATH when given by name only"),
+            "ens_file": path("The program to test"),
+            "reference_ens": path("Known-good program the tests are also run with, to compare the results"),
+            "serie_file": path("The serie file of the emulator, next to it by default"),
+            "assembler_cmd": command("Assembler invocation, with `{assembler}`, `{entry}`, `{out}` and `{src}` placeholders"),
+            "emulator_cmd": command("Emulator invocation, with `{emulator}`, `{serie}` and `{bin}` placeholders"),
+            "env": {
+                "description": "Extra environment variables for both the assembler and the emulator",
+                "type": ["object", "null"],
+                "additionalProperties": { "type": "string" }
+            }
+        }
+    })
+}
+
+fn group() -> Value {
+    json!({
+        "description": "A test, a list of tests, or a map with settings for the whole group and its `tests:`",
+        "if": { "type": "object", "required": ["tests"] },
+        "then": {
+            "type": "object",
+            "additionalProperties": false,
+            "properties": {
+                "points": { "description": "Points the whole group is worth, split between its tests by weight", "type": "number" },
+                "all_or_nothing": { "description": "Only give the group's points if all of its tests pass", "type": "boolean" },
+                "skip": { "description": "Don't run any test of the group, for this reason", "type": "string" },
+                "only": { "description": "Only run this group and the other tests marked like this", "type": "boolean" },
+                "xfail": { "description": "Every test of the group is expected to fail, for this reason", "type": "string" },
+                "defaults": { "description": "Values every test of the group starts from", "$ref": "#/definitions/test" },
+                "tests": { "type": "array", "items": { "$ref": "#/definitions/test" } }
+            }
+        },
+        "else": {
+            "anyOf": [
+                { "type": "array", "items": { "$ref": "#/definitions/test" } },
+                { "$ref": "#/definitions/test" }
+            ]
+        }
+    })
+}
+
+fn test() -> Value {
+    json!({
+        "description": "A map of checks, or of settings with `checks:`",
+        "if": { "type": "object", "required": ["cases"] },
+        "then": {
+            "description": "A template with `{column}` placeholders, run once per row of `cases:`",
+            "type": "object",
+            "properties": {
+                "cases": {
+                    "description": "A list of maps of values, or `columns:` with `rows:` of lists",
+                    "oneOf": [
+                        { "type": "array", "items": { "type": "object" } },
+                        {
+                            "type": "object",
+                            "additionalProperties": false,
+                            "required": ["columns", "rows"],
+                            "properties": {
+                                "columns": { "type": "array", "items": { "type": "string" } },
+                                "rows": { "type": "array", "items": { "type": "array" } }
+                            }
+                        }
+                    ]
+                }
+            }
+        },
+        "else": {
+            "anyOf": [
+                { "$ref": "#/definitions/checks" },
+                { "$ref": "#/definitions/settings" }
+            ]
+        }
+    })
+}
+
+fn settings() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "properties": {
+            "entrypoint": { "description": "The label the test runs from", "type": "string" },
+            "name": { "description": "Name of the test, `test_{group}{index}` by default", "type": "string" },
+            "setup": { "description": "Values set before running", "$ref": "#/definitions/checks" },
+            "checks": { "description": "Values expected after running", "$ref": "#/definitions/checks" },
+            "compare": {
+                "description": "Memory compared with the reference program besides the one in the checks, as `m(address): length`",
+                "type": "object",
+                "additionalProperties": false,
+                "patternProperties": { ADDRESS: { "type": "integer", "minimum": 0 } }
+            },
+            "generate": { "$ref": "#/definitions/generate" },
+            "points": { "description": "Points the test is worth when grading", "type": "number" },
+            "weight": { "description": "Share of the group's points, relative to the other tests of the group", "type": "number" },
+            "tags": { "description": "Labels to select tests by on the command line", "type": "array", "items": { "type": "string" } },
+            "skip": { "description": "Don't run the test, for this reason", "type": "string" },
+            "only": { "description": "Only run the tests marked like this", "type": "boolean" },
+            "xfail": { "description": "The test is expected to fail, for this reason", "type": "string" },
+            "snapshot": { "description": "`--bless` writes the values found into the checks", "type": "boolean" },
+            "use": { "description": "Fixtures the test starts from, in order", "$ref": "#/definitions/names" }
+        }
+    })
+}
+
+fn checks() -> Value {
+    json!({
+        "description": "Registers such as r2, and memory such as m(0x1000)",
+        "type": ["object", "null"],
+        "additionalProperties": false,
+        "patternProperties": {
+            REGISTER: { "$ref": "#/definitions/register" },
+            ADDRESS: { "$ref": "#/definitions/memory" }
+        }
+    })
+}
+
+fn memory() -> Value {
+    let number = |max: u64| json!({ "type": "integer", "minimum": 0, "maximum": max });
+    json!({
+        "oneOf": [
+            variant("bytes", "A list of bytes", json!({ "type": "array", "items": number(u8::MAX.into()) })),
+            variant("byte", "A byte", number(u8::MAX.into())),
+            variant("half_word", "A half word, at an address multiple of 2", number(u16::MAX.into())),
+            variant("word", "A word, at an address multiple of 4", number(u32::MAX.into())),
+            variant("double_word", "A double word, at an address multiple of 8", number(u64::MAX)),
+            variant("text", "Text, ended by a zero byte", json!({ "type": "string" }))
+        ]
+    })
+}
+
+fn generate() -> Value {
+    json!({
+        "description": "Random inputs to run the test with many times",
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["setup"],
+        "properties": {
+            "count": { "description": "How many cases are run", "type": "integer", "minimum": 0 },
+            "seed": { "description": "Seed for the cases of this test, instead of the one of the run", "type": "integer", "minimum": 0 },
+            "setup": {
+                "description": "Where each input goes, and the values it is picked from",
+                "type": "object",
+                "additionalProperties": false,
+                "patternProperties": {
+                    REGISTER: { "$ref": "#/definitions/domain" },
+                    ADDRESS: { "$ref": "#/definitions/domain" }
+                }
+            },
+            "checks": {
+                "description": "Expected register values, as expressions of the inputs",
+                "type": "object",
+                "additionalProperties": false,
+                "patternProperties": { REGISTER: { "type": ["string", "integer"] } }
+            }
+        }
+    })
+}
+
+fn domain() -> Value {
+    json!({
+        "oneOf": [
+            variant("range", "A number between both, included", json!({ "$ref": "#/definitions/range" })),
+            variant("array", "Numbers in memory", json!({
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["len", "range"],
+                "properties": {
+                    "len": { "$ref": "#/definitions/length" },
+                    "range": { "$ref": "#/definitions/range" },
+                    "size": { "enum": ["byte", "half_word", "word"] }
+                }
+            })),
+            variant("string", "Text in memory", json!({
+                "type": "object",
+                "additionalProperties": false,
+                "required": ["len"],
+                "properties": {
+                    "len": { "$ref": "#/definitions/length" },
+                    "chars": { "description": "The characters it is made of", "type": "string" }
+                }
+            })),
+            variant("len", "The length of the array or text at an address, as m(address)", json!({ "type": "string", "pattern": ADDRESS })),
+            variant("value", "A fixed value, such as the address of an array", json!({ "type": "integer" }))
+        ]
+    })
+}
+
+/// A map with only `kind` in it, as enums are written
+fn variant(kind: &str, description: &str, mut value: Value) -> Value {
+    value["description"] = description.into();
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": [kind],
+        "properties": { kind: value }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate::Generate,
+        validate::{CONFIG_KEYS, FILE_KEYS, GROUP_KEYS, MEMORY_KINDS, TEST_KEYS},
+    };
+
+    fn keys(properties: &Value) -> Vec<&str> {
+        let mut keys: Vec<_> = properties
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn sorted<'a>(keys: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+        let mut keys: Vec<_> = keys.into_iter().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    #[test]
+    fn has_the_keys_that_are_validated() {
+        let schema = schema();
+        let definitions = &schema["definitions"];
+        assert_eq!(keys(&schema["properties"]), sorted(FILE_KEYS));
+        assert_eq!(
+            keys(&definitions["config"]["properties"]),
+            sorted(CONFIG_KEYS)
+        );
+        assert_eq!(
+            keys(&definitions["group"]["then"]["properties"]),
+            sorted(GROUP_KEYS)
+        );
+        // `cases:` is in the templates instead
+        assert_eq!(
+            keys(&definitions["settings"]["properties"]),
+            sorted(TEST_KEYS.into_iter().filter(|k| *k != "cases"))
+        );
+        let kinds = definitions["memory"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|kind| kind["required"].as_array().unwrap())
+            .map(|kind| kind.as_str().unwrap());
+        assert_eq!(sorted(kinds), sorted(MEMORY_KINDS));
+    }
+
+    #[test]
+    fn lengths_load_as_the_schema_describes_them() {
+        for len in [json!(5), json!([1, 6])] {
+            let generate = json!({
+                "setup": {
+                    "m(0x1000)": { "array": { "len": len, "range": [0, 9] } },
+                    "m(0x2000)": { "string": { "len": len } }
+                }
+            });
+            serde_json::from_value::<Generate>(generate).unwrap();
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{runner::GroupResult, tests::Tests};
+
+/// Statuses of the tests in the previous runs, by `group/name`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LastRun {
+    tests: BTreeMap<String, String>,
+}
+
+impl LastRun {
+    /// The state of `tests.yml` is kept in `.tests.yml.last-run.json`, next to it
+    pub fn path_for(config: &Path) -> PathBuf {
+        let name = config.file_name().unwrap_or_default().to_string_lossy();
+        config.with_file_name(format!(".{name}.last-run.json"))
+    }
+
+    /// Reads the state, which is empty if there wasn't a previous run or it can't be read
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// Records the tests that ran, keeping what is known of the rest of
+    /// `all`. Tests that aren't there anymore are forgotten.
+    pub fn update(&mut self, groups: &[GroupResult], all: &Tests) {
+        let existing = all
+            .get_tests()
+            .flat_map(|(group, tests)| tests.map(move |(name, _)| format!("{group}/{name}")))
+            .collect::<HashSet<_>>();
+        self.tests.retain(|test, _| existing.contains(test));
+        for group in groups {
+            for (name, result) in &group.tests {
+                self.tests.insert(
+                    format!("{}/{name}", group.name),
+                    result.status().to_string(),
+                );
+            }
+        }
+    }
+
+    pub fn failed(&self, group: &str, name: &str) -> bool {
+        self.tests
+            .get(&format!("{group}/{name}"))
+            .is_some_and(|status| matches!(status.as_str(), "failed" | "error" | "xpassed"))
+    }
+
+    /// Moves the groups and tests that failed last time to the front, or with
+    /// `only_failed` drops the rest
+    pub fn prioritize<T>(&self, groups: &mut Vec<(String, Vec<(String, T)>)>, only_failed: bool) {
+        for (group, tests) in groups.iter_mut() {
+            if only_failed {
+                tests.retain(|(name, _)| self.failed(group, name));
+            } else {
+                tests.sort_by_key(|(name, _)| !self.failed(group, name));
+            }
+        }
+        groups.retain(|(_, tests)| !tests.is_empty());
+        groups
+            .sort_by_key(|(group, tests)| !tests.iter().any(|(name, _)| self.failed(group, name)));
+    }
+}
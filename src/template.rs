@@ -0,0 +1,34 @@
+use std::ffi::{OsStr, OsString};
+
+/// Argument layout used when the config doesn't provide an `assembler_cmd`.
+pub const DEFAULT_ASSEMBLER_CMD: &[&str] =
+    &["{assembler}", "-e", "{entry}", "-o", "{out}", "{src}"];
+/// Argument layout used when the config doesn't provide an `emulator_cmd`.
+pub const DEFAULT_EMULATOR_CMD: &[&str] = &["{emulator}", "-c", "{serie}", "{bin}"];
+
+pub fn default_command(template: &[&str]) -> Vec<String> {
+    template.iter().map(ToString::to_string).collect()
+}
+
+/// Replaces every `{name}` placeholder in the template with its value.
+///
+/// An argument consisting only of a placeholder is substituted verbatim, so paths
+/// that aren't valid UTF-8 survive. Unknown placeholders are left untouched.
+pub fn expand<S: AsRef<str>>(template: &[S], vars: &[(&str, &OsStr)]) -> Vec<OsString> {
+    template
+        .iter()
+        .map(|arg| {
+            let arg = arg.as_ref();
+            if let Some((_, value)) = vars.iter().find(|(name, _)| {
+                arg.strip_prefix('{').and_then(|a| a.strip_suffix('}')) == Some(name)
+            }) {
+                return value.to_os_string();
+            }
+            let mut arg = arg.to_string();
+            for (name, value) in vars {
+                arg = arg.replace(&format!("{{{name}}}"), &value.to_string_lossy());
+            }
+            arg.into()
+        })
+        .collect()
+}
@@ -1,11 +1,20 @@
-use std::{collections::HashMap, ops::Deref};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+};
 
 use serde::{
     de::{Error, Visitor},
     Deserialize,
 };
 
-use crate::emulator::{GPRegister, MemoryData};
+use crate::{
+    cases,
+    emulator::{GPRegister, MemoryData},
+    fixtures,
+    generate::Generate,
+    locate::Step,
+};
 
 #[derive(Debug, Clone)]
 pub enum TestCheck {
@@ -13,7 +22,7 @@ pub enum TestCheck {
     Memory(u32, MemoryData),
 }
 
-fn try_parse_hex_or_dec(s: &str) -> Option<u32> {
+pub fn try_parse_hex_or_dec(s: &str) -> Option<u32> {
     s.strip_prefix("0x")
         .map_or_else(|| s.parse().ok(), |end| u32::from_str_radix(end, 16).ok())
 }
@@ -31,7 +40,7 @@ impl<'de> Visitor<'de> for TestDataVisitor {
         A: serde::de::MapAccess<'de>,
     {
         let mut res = Vec::with_capacity(map.size_hint().unwrap_or(0));
-        while let Some(key) = map.next_key::<&str>()? {
+        while let Some(key) = map.next_key::<String>()? {
             if key.starts_with(['r', 'R']) {
                 let rn = key[1..]
                     .parse::<u8>()
@@ -65,7 +74,7 @@ impl<'de> Visitor<'de> for TestDataVisitor {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TestChecks(pub Vec<TestCheck>);
 
 impl<'de> Deserialize<'de> for TestChecks {
@@ -85,15 +94,85 @@ impl Deref for TestChecks {
     }
 }
 
+/// Memory to compare with the reference run, as `m(address): length` in bytes
+#[derive(Debug, Clone, Default)]
+pub struct MemoryRegions(pub Vec<(u32, u32)>);
+
+struct MemoryRegionsVisitor;
+impl<'de> Visitor<'de> for MemoryRegionsVisitor {
+    type Value = Vec<(u32, u32)>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "memory regions as m(address): length")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: serde::de::MapAccess<'de>,
+    {
+        let mut res = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(key) = map.next_key::<String>()? {
+            let addr = key
+                .strip_prefix(['m', 'M'])
+                .and_then(|k| k.strip_prefix('('))
+                .and_then(|k| k.strip_suffix(')'))
+                .and_then(try_parse_hex_or_dec)
+                .ok_or_else(|| {
+                    A::Error::invalid_type(
+                        serde::de::Unexpected::Other("unknown memory address"),
+                        &"a memory address as m(address)",
+                    )
+                })?;
+            res.push((addr, map.next_value()?));
+        }
+        Ok(res)
+    }
+}
+
+impl<'de> Deserialize<'de> for MemoryRegions {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(Self(deserializer.deserialize_map(MemoryRegionsVisitor)?))
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum TestData {
     NoSetup(TestChecks),
     WithSetup {
         entrypoint: Option<String>,
         name: Option<String>,
+        #[serde(default)]
         setup: TestChecks,
+        /// Can be left out when the test is compared with a reference program
+        #[serde(default)]
         checks: TestChecks,
+        /// Memory compared with the reference program besides the one in the checks
+        #[serde(default)]
+        compare: MemoryRegions,
+        /// Random inputs to run the test with many times
+        generate: Option<Box<Generate>>,
+        /// Points the test is worth when grading
+        points: Option<f64>,
+        /// Share of the group's points, relative to the other tests of the group
+        weight: Option<f64>,
+        /// Labels to select tests by on the command line
+        #[serde(default)]
+        tags: Vec<String>,
+        /// Don't run the test, for this reason
+        skip: Option<String>,
+        /// Only run the tests marked like this
+        #[serde(default)]
+        only: bool,
+        /// The test is expected to fail, for this reason
+        xfail: Option<String>,
+        /// `--bless` writes the values found into the checks
+        #[serde(default)]
+        snapshot: bool,
     },
 }
 
@@ -101,12 +180,130 @@ impl TestData {
     pub fn get_name(&self) -> Option<&str> {
         match self {
             Self::NoSetup(_) => None,
+            Self::WithSetup { name, .. } => name.as_deref(),
+        }
+    }
+
+    pub fn get_entrypoint(&self) -> Option<&str> {
+        match self {
+            Self::NoSetup(_) => None,
+            Self::WithSetup { entrypoint, .. } => entrypoint.as_deref(),
+        }
+    }
+
+    pub const fn get_points(&self) -> Option<f64> {
+        match self {
+            Self::NoSetup(_) => None,
+            Self::WithSetup { points, .. } => *points,
+        }
+    }
+
+    pub const fn get_weight(&self) -> Option<f64> {
+        match self {
+            Self::NoSetup(_) => None,
+            Self::WithSetup { weight, .. } => *weight,
+        }
+    }
+
+    pub fn get_tags(&self) -> &[String] {
+        match self {
+            Self::NoSetup(_) => &[],
+            Self::WithSetup { tags, .. } => tags,
+        }
+    }
+
+    pub fn get_skip(&self) -> Option<&str> {
+        match self {
+            Self::NoSetup(_) => None,
+            Self::WithSetup { skip, .. } => skip.as_deref(),
+        }
+    }
+
+    pub const fn is_only(&self) -> bool {
+        matches!(self, Self::WithSetup { only: true, .. })
+    }
+
+    pub fn get_setup(&self) -> &[TestCheck] {
+        match self {
+            Self::NoSetup(_) => &[],
+            Self::WithSetup { setup, .. } => setup,
+        }
+    }
+
+    pub fn get_compare(&self) -> &[(u32, u32)] {
+        match self {
+            Self::NoSetup(_) => &[],
+            Self::WithSetup { compare, .. } => &compare.0,
+        }
+    }
+
+    /// Whether the test has values to check, of its own or generated, which
+    /// it can fail on without a reference program
+    pub fn has_checks(&self) -> bool {
+        match self {
+            Self::NoSetup(checks) => !checks.is_empty(),
             Self::WithSetup {
-                entrypoint: _,
-                name,
-                setup: _,
-                checks: _,
-            } => name.as_deref(),
+                checks, generate, ..
+            } => !checks.is_empty() || generate.as_ref().is_some_and(|g| !g.checks.is_empty()),
+        }
+    }
+
+    pub fn get_generate(&self) -> Option<&Generate> {
+        match self {
+            Self::NoSetup(_) => None,
+            Self::WithSetup { generate, .. } => generate.as_deref(),
+        }
+    }
+
+    pub const fn is_snapshot(&self) -> bool {
+        matches!(self, Self::WithSetup { snapshot: true, .. })
+    }
+
+    pub fn get_xfail(&self) -> Option<&str> {
+        match self {
+            Self::NoSetup(_) => None,
+            Self::WithSetup { xfail, .. } => xfail.as_deref(),
+        }
+    }
+
+    /// Gives the test the markers of its group, unless it has its own
+    fn mark(
+        &mut self,
+        group_skip: &Option<String>,
+        group_only: bool,
+        group_xfail: &Option<String>,
+    ) {
+        if group_skip.is_none() && !group_only && group_xfail.is_none() {
+            return;
+        }
+        if let Self::NoSetup(checks) = self {
+            *self = Self::WithSetup {
+                entrypoint: None,
+                name: None,
+                setup: TestChecks::default(),
+                checks: std::mem::take(checks),
+                compare: MemoryRegions::default(),
+                generate: None,
+                points: None,
+                weight: None,
+                tags: vec![],
+                skip: None,
+                only: false,
+                xfail: None,
+                snapshot: false,
+            };
+        }
+        if let Self::WithSetup {
+            skip, only, xfail, ..
+        } = self
+        {
+            if skip.is_none() {
+                skip.clone_from(group_skip);
+            }
+            *only |= group_only;
+            if xfail.is_none() {
+                xfail.clone_from(group_xfail);
+            }
         }
     }
 }
@@ -114,40 +311,162 @@ impl TestData {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
 pub enum Test {
+    /// A list of tests with settings for the whole group. It goes first, as a
+    /// single test without checks takes any map.
+    Group {
+        /// Points the whole group is worth, split between its tests by weight
+        points: Option<f64>,
+        /// Only give the group's points if all of its tests pass
+        #[serde(default)]
+        all_or_nothing: bool,
+        /// Don't run any test of the group, for this reason
+        skip: Option<String>,
+        /// Only run this group and the other tests marked like this
+        #[serde(default)]
+        only: bool,
+        /// Every test of the group is expected to fail, for this reason
+        xfail: Option<String>,
+        tests: Vec<TestData>,
+    },
     Single(TestData),
     Multiple(Vec<TestData>),
 }
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(transparent)]
+impl Test {
+    /// The tests of the group named `group`, along with their names
+    pub fn tests<'a>(
+        &'a self,
+        group: &'a str,
+    ) -> Box<dyn Iterator<Item = (String, &'a TestData)> + 'a> {
+        match self {
+            Self::Single(test) => Box::new(std::iter::once((format!("test_{group}"), test))),
+            Self::Multiple(many) | Self::Group { tests: many, .. } => {
+                Box::new(many.iter().enumerate().map(move |(index, test)| {
+                    (
+                        test.get_name()
+                            .map_or_else(|| format!("test_{group}{index}"), ToString::to_string),
+                        test,
+                    )
+                }))
+            }
+        }
+    }
+
+    /// A name that more than one test of the group has
+    pub fn duplicate_name(&self, group: &str) -> Option<String> {
+        let mut names = HashSet::new();
+        self.tests(group)
+            .map(|(name, _)| name)
+            .find(|name| !names.insert(name.clone()))
+    }
+
+    pub const fn get_points(&self) -> Option<f64> {
+        match self {
+            Self::Group { points, .. } => *points,
+            _ => None,
+        }
+    }
+
+    pub const fn is_all_or_nothing(&self) -> bool {
+        matches!(
+            self,
+            Self::Group {
+                all_or_nothing: true,
+                ..
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Tests(HashMap<String, Test>);
 
 impl Tests {
+    /// Reads the groups of tests, once they are given their fixtures and
+    /// their tables are expanded. The errors name their group and where
+    /// they are in it.
+    pub fn new(
+        raw: HashMap<String, serde_yaml::Value>,
+        fixtures: &serde_yaml::Mapping,
+    ) -> Result<Self, Vec<(String, Vec<Step>, String)>> {
+        let mut groups = HashMap::with_capacity(raw.len());
+        let mut errors = vec![];
+        for (name, value) in raw {
+            let test = fixtures::apply(value, fixtures)
+                .and_then(|value| cases::expand(&name, value))
+                .and_then(|value| {
+                    serde_yaml::from_value::<Test>(value).map_err(|e| (vec![], e.to_string()))
+                })
+                .and_then(|test| match test.duplicate_name(&name) {
+                    Some(duplicate) => Err((vec![], format!("two tests are named {duplicate}"))),
+                    None => Ok(test),
+                });
+            match test {
+                Ok(test) => {
+                    groups.insert(name, test);
+                }
+                Err((path, e)) => errors.push((name, path, e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        for group in groups.values_mut() {
+            if let Test::Group {
+                skip,
+                only,
+                xfail,
+                tests,
+                ..
+            } = group
+            {
+                for test in tests {
+                    test.mark(skip, *only, xfail);
+                }
+            }
+        }
+        Ok(Self(groups))
+    }
+
+    /// Adds tests to a group, creating it if there is none. They can't go in
+    /// a group of a single test, nor share a name with a test of the group.
+    pub fn add(&mut self, group: &str, mut new: Vec<TestData>) -> Result<(), String> {
+        let test = self
+            .0
+            .entry(group.to_string())
+            .or_insert_with(|| Test::Multiple(vec![]));
+        match test {
+            Test::Single(_) => return Err(format!("group {group} is a single test")),
+            Test::Multiple(tests) => tests.append(&mut new),
+            Test::Group {
+                skip,
+                only,
+                xfail,
+                tests,
+                ..
+            } => {
+                for test in &mut new {
+                    test.mark(skip, *only, xfail);
+                }
+                tests.append(&mut new);
+            }
+        }
+        match test.duplicate_name(group) {
+            Some(name) => Err(format!("group {group} already has a test named {name}")),
+            None => Ok(()),
+        }
+    }
+
     pub fn get_tests(
         &self,
     ) -> impl Iterator<Item = (String, impl Iterator<Item = (String, &TestData)>)> {
         self.0
             .iter()
-            .map::<(String, Box<dyn Iterator<Item = _>>), _>(|(name, val)| {
-                (
-                    name.to_string(),
-                    match val {
-                        Test::Single(test) => {
-                            Box::new(std::iter::once((format!("test_{name}"), test)))
-                        }
-                        Test::Multiple(many) => {
-                            Box::new(many.iter().enumerate().map(move |(index, test)| {
-                                (
-                                    test.get_name().map_or_else(
-                                        || format!("test_{name}{index}"),
-                                        ToString::to_string,
-                                    ),
-                                    test,
-                                )
-                            }))
-                        }
-                    },
-                )
-            })
+            .map(|(name, val)| (name.to_string(), val.tests(name)))
+    }
+
+    /// The groups by name, with their settings
+    pub fn get_groups(&self) -> impl Iterator<Item = (&str, &Test)> {
+        self.0.iter().map(|(name, test)| (name.as_str(), test))
     }
 }
@@ -4,7 +4,7 @@ use std::{
     thread::{self, JoinHandle, Thread},
 };
 
-use crate::tests::TestData;
+use crate::{runner::TestResult, tests::TestData};
 
 pub struct ThreadPool<T, U> {
     // threads: Vec<ThreadData<T, U>>,
@@ -13,6 +13,7 @@ pub struct ThreadPool<T, U> {
     enqueue_tx: Sender<Enqueue<T>>,
     update_rx: Receiver<Update<usize>>,
     status: Vec<(usize, String)>,
+    events: Vec<Event>,
     n_finished: usize,
     n_sent: usize,
 }
@@ -25,7 +26,7 @@ enum Enqueue<T> {
 impl<T, U> ThreadPool<T, U>
 where
     T: Send + 'static + Name,
-    U: Send + 'static,
+    U: Send + 'static + Outcome,
 {
     pub fn new<J: Job<T, U> + 'static>(job: J, thread_n: usize) -> Self {
         Self::new_boxed(Box::new(job), thread_n)
@@ -96,7 +97,9 @@ where
 
                 for t in &mut threads {
                     while let Some((data, id)) = t.try_recv() {
-                        update_tx.send(Update::Finished(id)).unwrap();
+                        update_tx
+                            .send(Update::Finished(id, data.outcome()))
+                            .unwrap();
                         // println!("Finished {id}");
                         results[id] = Some(data);
                         to_complete -= 1;
@@ -127,6 +130,7 @@ where
             enqueue_tx,
             update_rx,
             status: Vec::with_capacity(thread_n),
+            events: Vec::new(),
             n_finished: 0,
             n_sent: 0,
         }
@@ -142,9 +146,22 @@ where
         while let Ok(data) = self.update_rx.try_recv() {
             changed = true;
             match data {
-                Update::Started(id, name) => self.status.push((id, name)),
-                Update::Finished(id) => {
+                Update::Started(id, name) => {
+                    self.events.push(Event::Started {
+                        id,
+                        name: name.clone(),
+                    });
+                    self.status.push((id, name))
+                }
+                Update::Finished(id, outcome) => {
                     self.n_finished += 1;
+                    if let Some((_, name)) = self.status.iter().find(|(id2, _)| &id == id2) {
+                        self.events.push(Event::Finished {
+                            id,
+                            name: name.clone(),
+                            outcome,
+                        });
+                    }
                     self.status.retain(|(id2, _)| &id != id2)
                 }
             }
@@ -157,6 +174,11 @@ where
         }
     }
 
+    /// Jobs started and finished since the last call, in order
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
     pub fn finish(&self) {
         self.enqueue_tx.send(Enqueue::Finish).unwrap();
     }
@@ -235,9 +257,44 @@ impl Name for (usize, String, String, TestData) {
     }
 }
 
+impl Name for (String, usize, String, String, TestData) {
+    fn name(&self) -> String {
+        format!("{}: {}/{}", self.0, self.2, self.3)
+    }
+}
+
+pub trait Outcome {
+    fn outcome(&self) -> String;
+}
+
+impl Outcome for (usize, String, String, TestResult) {
+    fn outcome(&self) -> String {
+        self.3.status().to_string()
+    }
+}
+
+impl Outcome for (String, usize, String, String, TestResult) {
+    fn outcome(&self) -> String {
+        self.4.status().to_string()
+    }
+}
+
 enum Update<Id> {
     Started(Id, String),
-    Finished(Id),
+    Finished(Id, String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Started {
+        id: usize,
+        name: String,
+    },
+    Finished {
+        id: usize,
+        name: String,
+        outcome: String,
+    },
 }
 
 pub enum UpdatedStatus {
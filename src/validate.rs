@@ -0,0 +1,541 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+use serde_yaml::{Mapping, Value};
+
+use crate::{
+    emulator::{GPRegister, MemoryData},
+    generate::Generate,
+    locate::{position, Step},
+    tests::try_parse_hex_or_dec,
+};
+
+pub const FILE_KEYS: [&str; 4] = ["config", "include", "fixtures", "tests"];
+pub const CONFIG_KEYS: [&str; 8] = [
+    "assembler",
+    "emulator",
+    "ens_file",
+    "reference_ens",
+    "serie_file",
+    "assembler_cmd",
+    "emulator_cmd",
+    "env",
+];
+pub const GROUP_KEYS: [&str; 7] = [
+    "points",
+    "all_or_nothing",
+    "skip",
+    "only",
+    "xfail",
+    "defaults",
+    "tests",
+];
+pub const TEST_KEYS: [&str; 15] = [
+    "entrypoint",
+    "name",
+    "setup",
+    "checks",
+    "compare",
+    "generate",
+    "points",
+    "weight",
+    "tags",
+    "skip",
+    "only",
+    "xfail",
+    "snapshot",
+    "use",
+    "cases",
+];
+pub const MEMORY_KINDS: [&str; 6] = ["bytes", "byte", "half_word", "word", "double_word", "text"];
+
+/// Something wrong in a test file, and where it is when that is known
+#[derive(Debug)]
+pub struct Problem {
+    pub file: PathBuf,
+    /// Line and column, from 1
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(file: &Path, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            position: None,
+            message,
+        }
+    }
+
+    /// A file that isn't YAML
+    pub fn syntax(file: &Path, e: &serde_yaml::Error) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            position: e.location().map(|l| (l.line(), l.column())),
+            message: without_location(e),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(
+                f,
+                "{}:{line}:{column}: {}",
+                self.file.display(),
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// Every problem in a test file, before its tests are put together
+pub fn file(path: &Path, yaml: &str, value: &Value) -> Vec<Problem> {
+    let mut checker = Checker::default();
+    checker.file(value);
+    let mut problems: Vec<_> = checker
+        .problems
+        .into_iter()
+        .map(|(steps, message)| Problem {
+            file: path.to_path_buf(),
+            position: Some(position(yaml, &steps)),
+            message,
+        })
+        .collect();
+    problems.sort_by_key(|problem| problem.position);
+    problems
+}
+
+/// Goes through a test file keeping the path to the value being checked
+#[derive(Default)]
+struct Checker {
+    path: Vec<Step>,
+    problems: Vec<(Vec<Step>, String)>,
+}
+
+impl Checker {
+    fn problem(&mut self, message: String) {
+        self.problems.push((self.path.clone(), message));
+    }
+
+    fn at<T>(&mut self, step: Step, check: impl FnOnce(&mut Self) -> T) -> T {
+        self.path.push(step);
+        let res = check(self);
+        self.path.pop();
+        res
+    }
+
+    fn key<T>(&mut self, key: &str, check: impl FnOnce(&mut Self) -> T) -> T {
+        self.at(Step::Key(key.to_string()), check)
+    }
+
+    /// Checks the value of `key` in `map`, if it is there
+    fn field(&mut self, map: &Mapping, key: &str, check: impl FnOnce(&mut Self, &Value)) {
+        if let Some(value) = map.get(key) {
+            self.key(key, |c| check(c, value));
+        }
+    }
+
+    /// Tells about the keys of `map` that aren't `known`, with the known key
+    /// they were probably meant to be
+    fn known_keys(&mut self, map: &Mapping, known: &[&str], of: &str) {
+        for key in map.keys() {
+            let Some(key) = key.as_str() else {
+                self.problem(format!("the keys of {of} have to be text, not {key:?}"));
+                continue;
+            };
+            if known.contains(&key) {
+                continue;
+            }
+            let message = match suggestion(key, known) {
+                Some(known) => format!("unknown key `{key}` in {of}, did you mean `{known}`?"),
+                None => format!(
+                    "unknown key `{key}` in {of}, the keys are: {}",
+                    known.join(", ")
+                ),
+            };
+            self.key(key, |c| c.problem(message));
+        }
+    }
+
+    /// Whether `value` can be read as a `T`, described as `what`
+    fn expect<T: DeserializeOwned>(&mut self, value: &Value, what: &str) -> Option<T> {
+        match read(value) {
+            Ok(res) => Some(res),
+            Err(_) => {
+                self.problem(format!("expected {what}, found {}", describe(value)));
+                None
+            }
+        }
+    }
+
+    fn map<'a>(&mut self, value: &'a Value, what: &str) -> Option<&'a Mapping> {
+        match value {
+            Value::Mapping(map) => Some(map),
+            value => {
+                self.problem(format!("expected {what}, found {}", describe(value)));
+                None
+            }
+        }
+    }
+
+    fn file(&mut self, value: &Value) {
+        if value.is_null() {
+            return;
+        }
+        let Some(file) = self.map(value, "a map with config:, include:, fixtures: and tests:")
+        else {
+            return;
+        };
+        self.known_keys(file, &FILE_KEYS, "the test file");
+        self.field(file, "config", Self::config);
+        self.field(file, "include", |c, include| {
+            if !include.is_string() {
+                c.expect::<Vec<String>>(include, "a file name or a list of them");
+            }
+        });
+        self.field(file, "fixtures", |c, fixtures| {
+            let Some(fixtures) = c.map(fixtures, "a map of fixtures by name") else {
+                return;
+            };
+            for (name, fixture) in fixtures {
+                let name = name.as_str().unwrap_or_default();
+                c.key(name, |c| c.test(fixture));
+            }
+        });
+        self.field(file, "tests", |c, tests| {
+            let Some(tests) = c.map(tests, "a map of groups of tests by name") else {
+                return;
+            };
+            for (name, group) in tests {
+                let name = name.as_str().unwrap_or_default();
+                c.key(name, |c| c.group(group));
+            }
+        });
+    }
+
+    fn config(&mut self, value: &Value) {
+        let Some(config) = self.map(value, "a map of settings") else {
+            return;
+        };
+        self.known_keys(config, &CONFIG_KEYS, "config");
+        for key in [
+            "assembler",
+            "emulator",
+            "ens_file",
+            "reference_ens",
+            "serie_file",
+        ] {
+            self.field(config, key, |c, path| {
+                c.expect::<PathBuf>(path, "a path");
+            });
+        }
+        for key in ["assembler_cmd", "emulator_cmd"] {
+            self.field(config, key, |c, cmd| {
+                c.expect::<Vec<String>>(cmd, "a command as a list of arguments");
+            });
+        }
+        self.field(config, "env", |c, env| {
+            c.expect::<BTreeMap<String, String>>(env, "a map of variables to their values");
+        });
+    }
+
+    fn group(&mut self, value: &Value) {
+        match value {
+            Value::Sequence(tests) => self.tests(tests),
+            Value::Mapping(group) if group.contains_key("tests") => {
+                self.known_keys(group, &GROUP_KEYS, "a group");
+                self.field(group, "points", |c, points| {
+                    c.expect::<f64>(points, "a number of points");
+                });
+                for key in ["all_or_nothing", "only"] {
+                    self.field(group, key, |c, flag| {
+                        c.expect::<bool>(flag, "true or false");
+                    });
+                }
+                for key in ["skip", "xfail"] {
+                    self.field(group, key, |c, reason| {
+                        c.expect::<String>(reason, "a reason");
+                    });
+                }
+                self.field(group, "defaults", Self::test);
+                self.field(group, "tests", |c, tests| match tests {
+                    Value::Sequence(tests) => c.tests(tests),
+                    tests => c.problem(format!(
+                        "expected a list of tests, found {}",
+                        describe(tests)
+                    )),
+                });
+            }
+            Value::Mapping(_) => self.test(value),
+            value => self.problem(format!(
+                "expected a test, a list of tests or a map with tests:, found {}",
+                describe(value)
+            )),
+        }
+    }
+
+    /// The names of the tests are checked once their tables are expanded
+    fn tests(&mut self, tests: &[Value]) {
+        for (index, test) in tests.iter().enumerate() {
+            self.at(Step::Index(index), |c| c.test(test));
+        }
+    }
+
+    fn test(&mut self, value: &Value) {
+        let Some(test) = self.map(
+            value,
+            "a test, as a map of checks or of settings with checks:",
+        ) else {
+            return;
+        };
+        if test.contains_key("cases") {
+            // The rest is a template, with values to fill in
+            self.known_keys(test, &TEST_KEYS, "a test");
+            return;
+        }
+        if test.keys().all(|k| k.as_str().is_some_and(is_check)) {
+            self.checks(test, false);
+            return;
+        }
+        for key in test
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|k| is_check(k))
+        {
+            self.key(key, |c| {
+                c.problem(format!(
+                    "{key} has to go under checks: or setup: in a test with settings"
+                ))
+            });
+        }
+        let settings = test
+            .iter()
+            .filter(|(k, _)| !k.as_str().is_some_and(is_check))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.known_keys(&settings, &TEST_KEYS, "a test");
+        for key in ["entrypoint", "name", "skip", "xfail"] {
+            self.field(test, key, |c, text| {
+                c.expect::<String>(text, "text");
+            });
+        }
+        for key in ["points", "weight"] {
+            self.field(test, key, |c, number| {
+                c.expect::<f64>(number, "a number");
+            });
+        }
+        for key in ["only", "snapshot"] {
+            self.field(test, key, |c, flag| {
+                c.expect::<bool>(flag, "true or false");
+            });
+        }
+        self.field(test, "tags", |c, tags| {
+            c.expect::<Vec<String>>(tags, "a list of tags");
+        });
+        self.field(test, "use", |c, fixtures| {
+            if !fixtures.is_string() {
+                c.expect::<Vec<String>>(fixtures, "a fixture name or a list of them");
+            }
+        });
+        for key in ["setup", "checks"] {
+            self.field(test, key, |c, checks| {
+                if let Some(checks) = c.map(checks, "a map of registers and memory") {
+                    c.checks(checks, key == "setup");
+                }
+            });
+        }
+        self.field(test, "compare", |c, compare| {
+            let Some(compare) = c.map(compare, "a map of m(address): length") else {
+                return;
+            };
+            for (key, length) in compare {
+                let key = key.as_str().unwrap_or_default();
+                c.key(key, |c| {
+                    if c.address(key).is_some() {
+                        c.expect::<u32>(length, "a length in bytes");
+                    }
+                });
+            }
+        });
+        self.field(test, "generate", |c, generate| {
+            if let Err(e) = read::<Generate>(generate) {
+                c.problem(e.to_string());
+            }
+        });
+    }
+
+    /// Checks registers and memory, which can be set up only in whole words
+    fn checks(&mut self, checks: &Mapping, setup: bool) {
+        for (key, value) in checks {
+            let Some(key) = key.as_str() else {
+                self.problem(format!("expected a register or memory, found {key:?}"));
+                continue;
+            };
+            self.key(key, |c| {
+                if key.starts_with(['r', 'R']) {
+                    c.register(key, value);
+                } else if key.starts_with(['m', 'M']) {
+                    if let (Some((address, data)), true) = (c.memory(key, value), setup) {
+                        if let Err(e) = setup_memory(address, &data) {
+                            c.problem(e);
+                        }
+                    }
+                } else {
+                    c.problem(format!(
+                        "unknown check `{key}`, checks are registers such as r2 or memory such as m(0x1000)"
+                    ));
+                }
+            });
+        }
+    }
+
+    fn register(&mut self, key: &str, value: &Value) {
+        if key[1..].parse().ok().and_then(GPRegister::new).is_none() {
+            self.problem(format!("{key} isn't a register, they go from r0 to r31"));
+            return;
+        }
+        self.expect::<u32>(value, "a register value, from 0 to 0xFFFFFFFF");
+    }
+
+    fn address(&mut self, key: &str) -> Option<u32> {
+        let address = key[1..]
+            .strip_prefix('(')
+            .and_then(|k| k.strip_suffix(')'))
+            .and_then(try_parse_hex_or_dec);
+        if address.is_none() {
+            self.problem(format!(
+                "{key} isn't a memory address, write it as m(0x1000) or m(4096)"
+            ));
+        }
+        address
+    }
+
+    fn memory(&mut self, key: &str, value: &Value) -> Option<(u32, MemoryData)> {
+        let address = self.address(key)?;
+        let data = self.map(
+            value,
+            "memory as one of bytes:, byte:, half_word:, word:, double_word: or text:",
+        )?;
+        self.known_keys(data, &MEMORY_KINDS, "memory");
+        if data.len() != 1 {
+            self.problem(format!(
+                "memory takes one of {}, found {}",
+                MEMORY_KINDS.join(", "),
+                data.len()
+            ));
+            return None;
+        }
+        let Some((Value::String(kind), _)) = data.iter().next() else {
+            return None;
+        };
+        let what = match kind.as_str() {
+            "bytes" => "a list of bytes",
+            "byte" => "a byte, from 0 to 0xFF",
+            "half_word" => "a half word, from 0 to 0xFFFF",
+            "word" => "a word, from 0 to 0xFFFFFFFF",
+            "double_word" => "a double word",
+            "text" => "text",
+            _ => return None,
+        };
+        let data = self.key(kind, |c| c.expect::<MemoryData>(value, what))?;
+        let alignment = match &data {
+            MemoryData::HalfWord(_) => 2,
+            MemoryData::Word(_) => 4,
+            MemoryData::DoubleWord(_) => 8,
+            _ => 1,
+        };
+        if address % alignment != 0 {
+            self.problem(format!(
+                "a {} has to be at an address multiple of {alignment}, not 0x{address:X}",
+                kind.replace('_', " ")
+            ));
+        }
+        Some((address, data))
+    }
+}
+
+/// Whether memory can be set up before running, which is done in whole words
+pub fn setup_memory(address: u32, data: &MemoryData) -> Result<(), String> {
+    if !address.is_multiple_of(4) {
+        return Err(format!(
+            "memory is set up in words, so it has to be at an address multiple of 4, not 0x{address:X}"
+        ));
+    }
+    data.words()
+        .map(|_| ())
+        .map_err(|()| "memory can only be set up as bytes, word, double_word or text".to_string())
+}
+
+/// Reads a value as the tests read it. YAML only takes enums, such as
+/// `word: 5`, as tags, so they go through JSON, which doesn't take nothing as
+/// an empty map or list as YAML does.
+fn read<T: DeserializeOwned>(value: &Value) -> Result<T, serde_json::Error> {
+    serde_yaml::from_value(value.clone())
+        .or_else(|_| serde_json::from_value(serde_json::to_value(value)?))
+}
+
+/// The message of an error, without where it is, which is given apart
+fn without_location(e: &serde_yaml::Error) -> String {
+    let message = e.to_string();
+    message
+        .split_once(" at line ")
+        .map_or(message.as_str(), |(m, _)| m)
+        .to_string()
+}
+
+/// Whether a key is a register or memory check
+fn is_check(key: &str) -> bool {
+    key.strip_prefix(['r', 'R'])
+        .is_some_and(|n| n.parse::<u8>().is_ok())
+        || key.starts_with("m(")
+        || key.starts_with("M(")
+}
+
+/// The known key closest to `key`, if any is close enough to be a typo of it
+fn suggestion<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (distance(key, k), *k))
+        .filter(|(d, k)| *d <= 2.min(k.len() / 2))
+        .min()
+        .map(|(_, k)| k)
+}
+
+/// Edits to turn `a` into `b`, counting swapping two letters as one
+fn distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<_>, Vec<_>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1)
+                .min(row[j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "nothing".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("{s:?}"),
+        Value::Sequence(_) => "a list".to_string(),
+        Value::Mapping(_) => "a map".to_string(),
+        Value::Tagged(tagged) => describe(&tagged.value),
+    }
+}
@@ -0,0 +1,27 @@
+use std::{fs, path::PathBuf, thread, time::Duration, time::SystemTime};
+
+/// How often the files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+fn modified(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Blocks until any of the files is modified, created or removed.
+///
+/// The files are polled instead of watched, so editors that save by replacing
+/// the file are noticed too.
+pub fn wait_for_change(paths: &[PathBuf]) {
+    let before = modified(paths);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if modified(paths) != before {
+            // Give the editor time to finish writing
+            thread::sleep(POLL_INTERVAL);
+            return;
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeepArtifacts {
+    /// Keep the working directory of every test
+    All,
+    /// Keep only the working directories of failing tests
+    Failed,
+}
+
+/// Unique directory where every test gets its own working directory.
+///
+/// It is removed when dropped, unless [`WorkDir::keep`] was called.
+#[derive(Debug)]
+pub struct WorkDir {
+    root: PathBuf,
+    keep: bool,
+}
+
+impl WorkDir {
+    /// Creates a new unique directory inside `parent`, or inside the system temp dir
+    pub fn new(parent: Option<&Path>) -> io::Result<Self> {
+        let parent = parent.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        fs::create_dir_all(&parent)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let mut attempt = 0u32;
+        loop {
+            let root = parent.join(format!(
+                "m88k-test-runner-{}-{nanos:x}-{attempt}",
+                std::process::id()
+            ));
+            match fs::create_dir(&root) {
+                Ok(()) => {
+                    return Ok(Self {
+                        root: root.canonicalize()?,
+                        keep: false,
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Creates the working directory of a single test inside `root`. Tests
+    /// whose names are the same once sanitized get a numbered one each, as
+    /// they may run at the same time.
+    pub fn create_test_dir(root: &Path, group: &str, test_name: &str) -> io::Result<PathBuf> {
+        let group_dir = root.join(sanitize(group));
+        fs::create_dir_all(&group_dir)?;
+        let name = sanitize(test_name);
+        let mut attempt = 0u32;
+        loop {
+            let dir = match attempt {
+                0 => group_dir.join(&name),
+                n => group_dir.join(format!("{name}-{n}")),
+            };
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Removes the directory if the process gets interrupted
+    pub fn remove_on_interrupt(&self) -> Result<(), ctrlc::Error> {
+        let root = self.root.clone();
+        ctrlc::set_handler(move || {
+            let _ = fs::remove_dir_all(&root);
+            std::process::exit(130);
+        })
+    }
+
+    /// Leaves the directory on disk after the run
+    pub fn keep(&mut self) {
+        self.keep = true;
+    }
+
+    /// Leaves the directory on disk if the artifacts are kept: those of all
+    /// tests, or those of the failed ones when any failed. Tells if it does.
+    pub fn keep_artifacts(&mut self, artifacts: Option<KeepArtifacts>, any_failed: bool) -> bool {
+        match artifacts {
+            Some(KeepArtifacts::All) => self.keep(),
+            Some(KeepArtifacts::Failed) if any_failed => self.keep(),
+            _ => {}
+        }
+        self.keep
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+}
+
+/// Replaces everything but `[A-Za-z0-9_-]` so the name can be used as a directory
+pub fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}